@@ -0,0 +1,439 @@
+use std::f64::consts::PI;
+use vector3::Vector3;
+
+use crate::color::Color;
+use crate::object::{Coloration, Principled, SurfaceCoords};
+use crate::sampling::{cosine_hemisphere, orthonormal_basis, Rng};
+
+/// Smallest GGX alpha, perfectly smooth microfacet surfaces make the distribution a delta
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Scattering at a surface point.
+/// All directions point away from the surface, `normal` is the unit shading normal.
+#[derive(Clone)]
+pub enum Bsdf {
+    /// Ideal diffuse reflection
+    Lambert { color: Color },
+    /// Retro-reflection growing towards grazing angles, Disney's sheen
+    Sheen { color: Color },
+    /// Rough metal with Schlick's Fresnel, `f0` is the reflectance at normal incidence
+    Conductor { f0: Color, alpha: f64 },
+    /// Rough glass, `normal` points outside; transmitted light is tinted by `tint`.
+    /// `index` is relative, the index inside over the one outside.
+    Dielectric { tint: Color, alpha: f64, index: f64 },
+    /// Weighted sum of lobes, sampled by picking one lobe per sample
+    Mix(Vec<(f32, Bsdf)>),
+}
+
+/// Direction sampled from a `Bsdf`
+pub struct BsdfSample {
+    pub direction: Vector3,
+    /// BSDF value times the cosine, divided by the density
+    pub weight: Color,
+    /// Density per unit solid angle
+    pub pdf: f64,
+}
+
+/// GGX alpha of a perceptual roughness in [0, 1]
+pub fn roughness_to_alpha(roughness: f32) -> f64 {
+    (roughness as f64 * roughness as f64).max(MIN_ALPHA)
+}
+
+/// Lobes of the principled material at a point with the given base color, in a medium with the index `outside`
+pub fn principled(base_color: Color, principled: &Principled, coords: &SurfaceCoords, outside: f32) -> Bsdf {
+    let parameter = |coloration: &Coloration| coloration.value(coords).clamp(0.0, 1.0);
+    let metallic = parameter(&principled.metallic);
+    let transmission = parameter(&principled.transmission);
+    let alpha = roughness_to_alpha(parameter(&principled.roughness));
+    let specular = parameter(&principled.specular);
+    let clearcoat = parameter(&principled.clearcoat);
+    let sheen = parameter(&principled.sheen);
+
+    // metals tint their reflection with the base color, dielectrics reflect white
+    let f0 = Color::WHITE * (0.08 * specular * (1.0 - metallic)) + base_color * metallic;
+    let opaque_dielectric = (1.0 - metallic) * (1.0 - transmission);
+    let lobes = vec![
+        (opaque_dielectric, Bsdf::Lambert { color: base_color }),
+        (opaque_dielectric, Bsdf::Sheen { color: Color::WHITE * sheen }),
+        // the transmitting part reflects through its own Fresnel term
+        (1.0 - (1.0 - metallic) * transmission, Bsdf::Conductor { f0, alpha }),
+        (
+            (1.0 - metallic) * transmission,
+            Bsdf::Dielectric { tint: base_color, alpha, index: (principled.index / outside) as f64 },
+        ),
+        (
+            clearcoat,
+            Bsdf::Conductor {
+                f0: Color::WHITE * 0.04,
+                alpha: roughness_to_alpha(parameter(&principled.clearcoat_roughness)),
+            },
+        ),
+    ];
+    Bsdf::Mix(lobes.into_iter().filter(|(weight, _)| *weight > 0.0).collect())
+}
+
+impl Bsdf {
+    /// The BSDF without its diffuse lobes, for tracers that only follow glossy bounces
+    pub fn glossy(&self) -> Bsdf {
+        match self {
+            Bsdf::Lambert { .. } | Bsdf::Sheen { .. } => Bsdf::Mix(Vec::new()),
+            Bsdf::Mix(lobes) => Bsdf::Mix(
+                lobes
+                    .iter()
+                    .filter(|(_, lobe)| !matches!(lobe, Bsdf::Lambert { .. } | Bsdf::Sheen { .. }))
+                    .cloned()
+                    .collect(),
+            ),
+            bsdf => bsdf.clone(),
+        }
+    }
+
+    /// BSDF value times |cos| of the incoming direction `wi`
+    pub fn eval(&self, normal: &Vector3, wo: &Vector3, wi: &Vector3) -> Color {
+        match self {
+            Bsdf::Lambert { color } => {
+                let frame = Frame::new(facing(normal, wo));
+                let cos = frame.local(wi).z;
+                if cos <= 0.0 {
+                    return Color::BLACK;
+                }
+                *color * (cos / PI) as f32
+            }
+            Bsdf::Sheen { color } => {
+                let normal = facing(normal, wo);
+                let cos = normal.dot(wi);
+                if cos <= 0.0 {
+                    return Color::BLACK;
+                }
+                let half = (*wo + *wi).normalize();
+                *color * ((1.0 - wi.dot(&half).clamp(0.0, 1.0)).powi(5) * cos) as f32
+            }
+            Bsdf::Conductor { f0, alpha } => {
+                let frame = Frame::new(facing(normal, wo));
+                let (wo, wi) = (frame.local(wo), frame.local(wi));
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return Color::BLACK;
+                }
+                let wm = (wo + wi).normalize();
+                let specular = ggx_d(&wm, *alpha) * ggx_g(&wo, &wi, *alpha) / (4.0 * wo.z);
+                schlick(*f0, wo.dot(&wm)) * specular as f32
+            }
+            Bsdf::Dielectric { tint, alpha, index } => {
+                let frame = Frame::new(*normal);
+                let (wo, wi) = (frame.local(wo), frame.local(wi));
+                let Some((wm, etap)) = generalized_half_vector(&wo, &wi, *index) else {
+                    return Color::BLACK;
+                };
+                let fresnel = fresnel_dielectric(wo.dot(&wm), *index);
+                let d = ggx_d(&wm, *alpha);
+                let g = ggx_g(&wo, &wi, *alpha);
+                if wo.z * wi.z > 0.0 {
+                    Color::WHITE * (d * g * fresnel / (4.0 * wo.z.abs())) as f32
+                } else {
+                    let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2) * wo.z.abs();
+                    let value = d * g * (1.0 - fresnel) * (wi.dot(&wm) * wo.dot(&wm) / denominator).abs();
+                    // radiance gets compressed into a smaller solid angle when entering the denser medium
+                    *tint * (value / (etap * etap)) as f32
+                }
+            }
+            Bsdf::Mix(lobes) => lobes.iter().fold(Color::BLACK, |color, (weight, lobe)| {
+                color + lobe.eval(normal, wo, wi) * *weight
+            }),
+        }
+    }
+
+    /// Density of `sample` choosing `wi`
+    pub fn pdf(&self, normal: &Vector3, wo: &Vector3, wi: &Vector3) -> f64 {
+        match self {
+            Bsdf::Lambert { .. } | Bsdf::Sheen { .. } => (facing(normal, wo).dot(wi) / PI).max(0.0),
+            Bsdf::Conductor { alpha, .. } => {
+                let frame = Frame::new(facing(normal, wo));
+                let (wo, wi) = (frame.local(wo), frame.local(wi));
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return 0.0;
+                }
+                let wm = (wo + wi).normalize();
+                visible_normal_pdf(&wo, &wm, *alpha) / (4.0 * wo.dot(&wm).abs())
+            }
+            Bsdf::Dielectric { alpha, index, .. } => {
+                let frame = Frame::new(*normal);
+                let (wo, wi) = (frame.local(wo), frame.local(wi));
+                let Some((wm, etap)) = generalized_half_vector(&wo, &wi, *index) else {
+                    return 0.0;
+                };
+                let reflectance = fresnel_dielectric(wo.dot(&wm), *index);
+                let normal_pdf = visible_normal_pdf(&wo, &wm, *alpha);
+                if wo.z * wi.z > 0.0 {
+                    normal_pdf / (4.0 * wo.dot(&wm).abs()) * reflectance
+                } else {
+                    let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2);
+                    normal_pdf * wi.dot(&wm).abs() / denominator * (1.0 - reflectance)
+                }
+            }
+            Bsdf::Mix(lobes) => match self.lobe_probabilities(normal, wo) {
+                Some(probabilities) => {
+                    lobes.iter().zip(probabilities).map(|((_, lobe), p)| p * lobe.pdf(normal, wo, wi)).sum()
+                }
+                None => 0.0,
+            },
+        }
+    }
+
+    pub fn sample(&self, normal: &Vector3, wo: &Vector3, rng: &mut Rng) -> Option<BsdfSample> {
+        match self {
+            Bsdf::Lambert { color } => {
+                let normal = facing(normal, wo);
+                let direction = cosine_hemisphere(normal, rng);
+                let pdf = normal.dot(&direction) / PI;
+                // the cosine and 1/pi cancel with the density
+                (pdf > 0.0).then_some(BsdfSample { direction, weight: *color, pdf })
+            }
+            Bsdf::Sheen { .. } => {
+                let direction = cosine_hemisphere(facing(normal, wo), rng);
+                let pdf = self.pdf(normal, wo, &direction);
+                (pdf > 0.0).then(|| BsdfSample { direction, weight: self.eval(normal, wo, &direction) * (1.0 / pdf) as f32, pdf })
+            }
+            Bsdf::Conductor { f0, alpha } => {
+                let frame = Frame::new(facing(normal, wo));
+                let wo = frame.local(wo);
+                if wo.z <= 0.0 {
+                    return None;
+                }
+                let wm = sample_visible_normal(&wo, *alpha, rng.next_f64(), rng.next_f64());
+                let wi = reflect(&wo, &wm);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                let pdf = visible_normal_pdf(&wo, &wm, *alpha) / (4.0 * wo.dot(&wm).abs());
+                let weight = schlick(*f0, wo.dot(&wm)) * (ggx_g(&wo, &wi, *alpha) / ggx_g1(&wo, *alpha)) as f32;
+                Some(BsdfSample { direction: frame.world(&wi), weight, pdf })
+            }
+            Bsdf::Dielectric { tint, alpha, index } => {
+                let frame = Frame::new(*normal);
+                let wo = frame.local(wo);
+                if wo.z == 0.0 {
+                    return None;
+                }
+                let wm = sample_visible_normal(&wo, *alpha, rng.next_f64(), rng.next_f64());
+                let normal_pdf = visible_normal_pdf(&wo, &wm, *alpha);
+                let reflectance = fresnel_dielectric(wo.dot(&wm), *index);
+                // visible normal sampling leaves G(wo, wi) / G1(wo) as the weight
+                let masking = |wi: &Vector3| ggx_g(&wo, wi, *alpha) / ggx_g1(&wo, *alpha);
+
+                if rng.next_f64() < reflectance {
+                    let wi = reflect(&wo, &wm);
+                    if wo.z * wi.z <= 0.0 {
+                        return None;
+                    }
+                    let pdf = normal_pdf / (4.0 * wo.dot(&wm).abs()) * reflectance;
+                    Some(BsdfSample { direction: frame.world(&wi), weight: Color::WHITE * masking(&wi) as f32, pdf })
+                } else {
+                    let (wi, etap) = refract(&wo, &wm, *index)?;
+                    if wo.z * wi.z >= 0.0 {
+                        return None;
+                    }
+                    let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2);
+                    let pdf = normal_pdf * wi.dot(&wm).abs() / denominator * (1.0 - reflectance);
+                    let weight = *tint * (masking(&wi) / (etap * etap)) as f32;
+                    Some(BsdfSample { direction: frame.world(&wi), weight, pdf })
+                }
+            }
+            Bsdf::Mix(lobes) => {
+                let probabilities = self.lobe_probabilities(normal, wo)?;
+                let mut u = rng.next_f64();
+                let index = probabilities
+                    .iter()
+                    .position(|p| {
+                        u -= p;
+                        u < 0.0
+                    })
+                    .unwrap_or(lobes.len() - 1);
+                let direction = lobes[index].1.sample(normal, wo, rng)?.direction;
+                // one-sample estimate over all the lobes that could have picked the direction
+                let pdf = self.pdf(normal, wo, &direction);
+                (pdf > 0.0).then(|| BsdfSample { direction, weight: self.eval(normal, wo, &direction) * (1.0 / pdf) as f32, pdf })
+            }
+        }
+    }
+
+    /// Chance of each lobe of a mix being sampled, proportional to its expected contribution
+    fn lobe_probabilities(&self, normal: &Vector3, wo: &Vector3) -> Option<Vec<f64>> {
+        let Bsdf::Mix(lobes) = self else {
+            return None;
+        };
+        let cos = normal.dot(wo);
+        let weights: Vec<f64> = lobes
+            .iter()
+            .map(|(weight, lobe)| {
+                let albedo = match lobe {
+                    Bsdf::Lambert { color } | Bsdf::Sheen { color } => color.luminance(),
+                    Bsdf::Conductor { f0, .. } => schlick(*f0, cos).luminance(),
+                    Bsdf::Dielectric { .. } | Bsdf::Mix(_) => 1.0,
+                };
+                (*weight * albedo) as f64
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        (total > 0.0).then(|| weights.iter().map(|weight| weight / total).collect())
+    }
+}
+
+/// Orthonormal frame with `normal` as the z axis
+struct Frame {
+    tangent: Vector3,
+    bitangent: Vector3,
+    normal: Vector3,
+}
+
+impl Frame {
+    fn new(normal: Vector3) -> Frame {
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        Frame { tangent, bitangent, normal }
+    }
+
+    fn local(&self, v: &Vector3) -> Vector3 {
+        Vector3 { x: v.dot(&self.tangent), y: v.dot(&self.bitangent), z: v.dot(&self.normal) }
+    }
+
+    fn world(&self, v: &Vector3) -> Vector3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// `normal` flipped to the side of `w`
+fn facing(normal: &Vector3, w: &Vector3) -> Vector3 {
+    if normal.dot(w) < 0.0 {
+        Vector3::zero() - *normal
+    } else {
+        *normal
+    }
+}
+
+fn reflect(wo: &Vector3, wm: &Vector3) -> Vector3 {
+    *wm * (2.0 * wo.dot(wm)) - *wo
+}
+
+/// Refracts `wo` through the microfacet `wm`, `index` is the index inside (below the surface).
+/// Returns the direction and the relative index of the side it goes to, `None` on total internal reflection.
+fn refract(wo: &Vector3, wm: &Vector3, index: f64) -> Option<(Vector3, f64)> {
+    let (mut n, mut eta, mut cos_i) = (*wm, index, wo.dot(wm));
+    if cos_i < 0.0 {
+        // leaving the medium
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        n = Vector3::zero() - n;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((Vector3::zero() - *wo * (1.0 / eta) + n * (cos_i / eta - cos_t), eta))
+}
+
+/// Microfacet normal of the pair (wo, wi) in the upper hemisphere together with the relative index
+/// of refraction `etap` (1 for reflection), `None` for degenerate or back-facing configurations
+fn generalized_half_vector(wo: &Vector3, wi: &Vector3, index: f64) -> Option<(Vector3, f64)> {
+    let etap = if wo.z * wi.z > 0.0 {
+        1.0
+    } else if wo.z > 0.0 {
+        index
+    } else {
+        1.0 / index
+    };
+    let wm = *wi * etap + *wo;
+    if wo.z == 0.0 || wi.z == 0.0 || wm.dot(&wm) == 0.0 {
+        return None;
+    }
+    let wm = facing(&wm.normalize(), &Vector3 { x: 0.0, y: 0.0, z: 1.0 });
+    // microfacets seen from behind don't contribute
+    if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+        return None;
+    }
+    Some((wm, etap))
+}
+
+/// Fresnel reflectance of an unpolarized ray hitting a dielectric with `index` inside,
+/// `cos_i` is negative for rays coming from inside
+/// https://en.wikipedia.org/wiki/Fresnel_equations
+pub fn fresnel_dielectric(cos_i: f64, index: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / index) } else { (cos_i, index) };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// Schlick's approximation with a colored reflectance at normal incidence
+/// https://en.wikipedia.org/wiki/Schlick%27s_approximation
+pub fn schlick(f0: Color, cos: f64) -> Color {
+    let k = (1.0 - cos.abs()).clamp(0.0, 1.0).powi(5) as f32;
+    f0 * (1.0 - k) + Color::WHITE * k
+}
+
+// GGX (Trowbridge-Reitz) microfacet distribution, in the local frame: vvv
+// https://jcgt.org/published/0003/02/03/ (Heitz, Understanding the Masking-Shadowing Function)
+
+fn ggx_d(wm: &Vector3, alpha: f64) -> f64 {
+    let cos2 = wm.z * wm.z;
+    let alpha2 = alpha * alpha;
+    let denominator = cos2 * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+/// Smith's auxiliary function
+fn ggx_lambda(w: &Vector3, alpha: f64) -> f64 {
+    let cos2 = w.z * w.z;
+    if cos2 == 0.0 {
+        return f64::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) / 2.0
+}
+
+/// Smith masking of a single direction
+fn ggx_g1(w: &Vector3, alpha: f64) -> f64 {
+    1.0 / (1.0 + ggx_lambda(w, alpha))
+}
+
+/// Height-correlated Smith masking-shadowing
+fn ggx_g(wo: &Vector3, wi: &Vector3, alpha: f64) -> f64 {
+    1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+
+/// Density of the normals visible from `w`
+fn visible_normal_pdf(w: &Vector3, wm: &Vector3, alpha: f64) -> f64 {
+    ggx_g1(w, alpha) / w.z.abs() * ggx_d(wm, alpha) * w.dot(wm).abs()
+}
+
+/// Samples a microfacet normal visible from `w`
+/// https://jcgt.org/published/0007/04/01/ (Heitz, Sampling the GGX Distribution of Visible Normals)
+fn sample_visible_normal(w: &Vector3, alpha: f64, u1: f64, u2: f64) -> Vector3 {
+    // stretch the view direction to the hemisphere configuration
+    let mut wh = Vector3 { x: alpha * w.x, y: alpha * w.y, z: w.z }.normalize();
+    if wh.z < 0.0 {
+        wh = Vector3::zero() - wh;
+    }
+    let length2 = wh.x * wh.x + wh.y * wh.y;
+    let t1 = if length2 > 0.0 {
+        Vector3 { x: -wh.y, y: wh.x, z: 0.0 } * (1.0 / length2.sqrt())
+    } else {
+        Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let t2 = wh.cross(&t1);
+
+    // uniform point on the disk, warped to the visible part of the hemisphere
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = (1.0 + wh.z) / 2.0;
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // back to the ellipsoid configuration
+    Vector3 { x: alpha * nh.x, y: alpha * nh.y, z: nh.z.max(1e-6) }.normalize()
+}
@@ -0,0 +1,434 @@
+use vector3::Vector3;
+
+use crate::point::Point;
+use crate::rendering::Ray;
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point { x: f64::INFINITY, y: f64::INFINITY, z: f64::INFINITY },
+            max: Point { x: f64::NEG_INFINITY, y: f64::NEG_INFINITY, z: f64::NEG_INFINITY },
+        }
+    }
+
+    pub fn from_points(points: &[Point]) -> Aabb {
+        points.iter().fold(Aabb::empty(), |aabb, point| aabb.grow(point))
+    }
+
+    pub fn grow(&self, point: &Point) -> Aabb {
+        Aabb {
+            min: Point { x: self.min.x.min(point.x), y: self.min.y.min(point.y), z: self.min.z.min(point.z) },
+            max: Point { x: self.max.x.max(point.x), y: self.max.y.max(point.y), z: self.max.z.max(point.z) },
+        }
+    }
+
+    /// Smallest box around both, empty boxes add nothing
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let (a, b) = (self, other);
+        Aabb {
+            min: Point { x: a.min.x.min(b.min.x), y: a.min.y.min(b.min.y), z: a.min.z.min(b.min.z) },
+            max: Point { x: a.max.x.max(b.max.x), y: a.max.y.max(b.max.y), z: a.max.z.max(b.max.z) },
+        }
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point {
+            x: 0.5 * (self.min.x + self.max.x),
+            y: 0.5 * (self.min.y + self.max.y),
+            z: 0.5 * (self.min.z + self.max.z),
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Slab test, returns the distance at which the ray enters the box
+    fn intersect(&self, ray: &Ray, inv_direction: &Vector3) -> Option<f64> {
+        let mut t_min = 0.0f64;
+        let mut t_max = f64::INFINITY;
+        for axis in 0..3 {
+            let t1 = (axis_of(&self.min, axis) - axis_of(&ray.origin, axis)) * axis_of_vec(inv_direction, axis);
+            let t2 = (axis_of(&self.max, axis) - axis_of(&ray.origin, axis)) * axis_of_vec(inv_direction, axis);
+            // `max`/`min` ignore the NaN coming from a parallel ray starting on a slab
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+        }
+        // conservative bound against rounding errors, so grazing hits are never culled
+        if t_min <= t_max * (1.0 + 4.0 * f64::EPSILON) {
+            Some(t_min)
+        } else {
+            None
+        }
+    }
+}
+
+fn axis_of(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+fn axis_of_vec(vector: &Vector3, axis: usize) -> f64 {
+    match axis {
+        0 => vector.x,
+        1 => vector.y,
+        _ => vector.z,
+    }
+}
+
+/// Bounding volume hierarchy over a list of primitives, built with the surface area heuristic
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, leaves refer to contiguous ranges of this list
+    indices: Vec<usize>,
+}
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { left: usize, right: usize },
+}
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+/// Cost of a ray-box test relative to a ray-primitive test
+const TRAVERSAL_COST: f64 = 0.5;
+
+impl Bvh {
+    /// Builds the hierarchy from the bounds of every primitive
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centroids: Vec<Point> = bounds.iter().map(|b| b.centroid()).collect();
+            bvh.build_node(bounds, &centroids, 0, bounds.len());
+        }
+        bvh
+    }
+
+    /// Recursively builds the node for `indices[first..first + count]`, returns its index
+    fn build_node(&mut self, bounds: &[Aabb], centroids: &[Point], first: usize, count: usize) -> usize {
+        let range = first..first + count;
+        let node_bounds = self.indices[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i]));
+        let centroid_bounds = self.indices[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.grow(&centroids[i]));
+
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            kind: NodeKind::Leaf { first, count },
+        });
+        if count <= 1 {
+            return node;
+        }
+
+        let Some((axis, split)) = find_split(
+            &self.indices[range.clone()],
+            bounds,
+            centroids,
+            &node_bounds,
+            &centroid_bounds,
+        ) else {
+            return node;
+        };
+
+        let (min, extent) = (
+            axis_of(&centroid_bounds.min, axis),
+            axis_of(&centroid_bounds.max, axis) - axis_of(&centroid_bounds.min, axis),
+        );
+        let mut left_count = 0;
+        for i in range.clone() {
+            let index = self.indices[i];
+            if bin_of(axis_of(&centroids[index], axis), min, extent) < split {
+                self.indices.swap(first + left_count, i);
+                left_count += 1;
+            }
+        }
+
+        let left = self.build_node(bounds, centroids, first, left_count);
+        let right = self.build_node(bounds, centroids, first + left_count, count - left_count);
+        self.nodes[node].kind = NodeKind::Interior { left, right };
+        node
+    }
+
+    /// Returns the nearest hit reported by `intersect` for the primitive with the given index.
+    /// Hits at equal distances resolve to the primitive with the lower index,
+    /// so the result matches a linear search over all primitives.
+    pub fn intersect<T>(
+        &self,
+        ray: &Ray,
+        mut intersect: impl FnMut(usize) -> Option<(f64, T)>,
+    ) -> Option<(f64, usize, T)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Vector3 {
+            x: 1.0 / ray.direction.x,
+            y: 1.0 / ray.direction.y,
+            z: 1.0 / ray.direction.z,
+        };
+
+        let mut nearest: Option<(f64, usize, T)> = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            match node.bounds.intersect(ray, &inv_direction) {
+                Some(distance) if nearest.as_ref().is_none_or(|n| distance <= n.0) => {}
+                _ => continue,
+            }
+
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for &index in &self.indices[first..first + count] {
+                        let Some((distance, hit)) = intersect(index) else {
+                            continue;
+                        };
+                        if distance.is_nan() {
+                            continue;
+                        }
+                        let closer = match &nearest {
+                            Some((d, i, _)) => distance < *d || (distance == *d && index < *i),
+                            None => true,
+                        };
+                        if closer {
+                            nearest = Some((distance, index, hit));
+                        }
+                    }
+                }
+                NodeKind::Interior { left, right } => {
+                    // visit the nearer child first
+                    let left_distance = self.nodes[left].bounds.intersect(ray, &inv_direction);
+                    let right_distance = self.nodes[right].bounds.intersect(ray, &inv_direction);
+                    if left_distance.unwrap_or(f64::INFINITY) <= right_distance.unwrap_or(f64::INFINITY) {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+fn bin_of(value: f64, min: f64, extent: f64) -> usize {
+    (((value - min) / extent * BINS as f64) as usize).min(BINS - 1)
+}
+
+/// Binned SAH: returns the axis and the first bin of the right child,
+/// or `None` if keeping the primitives in a leaf is cheaper
+fn find_split(
+    indices: &[usize],
+    bounds: &[Aabb],
+    centroids: &[Point],
+    node_bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<(usize, usize)> {
+    let leaf_cost = indices.len() as f64;
+    let mut best: Option<(f64, usize, usize)> = None;
+
+    for axis in 0..3 {
+        let min = axis_of(&centroid_bounds.min, axis);
+        let extent = axis_of(&centroid_bounds.max, axis) - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [(Aabb::empty(), 0usize); BINS];
+        for &index in indices {
+            let bin = &mut bins[bin_of(axis_of(&centroids[index], axis), min, extent)];
+            bin.0 = bin.0.union(&bounds[index]);
+            bin.1 += 1;
+        }
+
+        // sweep from the right to get the bounds of every right-hand side
+        let mut right_areas = [0.0; BINS];
+        let mut right_counts = [0usize; BINS];
+        let mut accumulated = (Aabb::empty(), 0);
+        for split in (1..BINS).rev() {
+            accumulated = (accumulated.0.union(&bins[split].0), accumulated.1 + bins[split].1);
+            right_areas[split] = accumulated.0.surface_area();
+            right_counts[split] = accumulated.1;
+        }
+
+        let mut left = (Aabb::empty(), 0);
+        for split in 1..BINS {
+            left = (left.0.union(&bins[split - 1].0), left.1 + bins[split - 1].1);
+            if left.1 == 0 || right_counts[split] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (left.0.surface_area() * left.1 as f64 + right_areas[split] * right_counts[split] as f64)
+                    / node_bounds.surface_area().max(f64::MIN_POSITIVE);
+            if best.is_none_or(|b| cost < b.0) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, axis, split)) if cost < leaf_cost || indices.len() > MAX_LEAF_SIZE => Some((axis, split)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::object::{Face, Material, Mesh, Object, Plane, Sphere, Surface, Triangle};
+    use crate::rendering::Intersectable;
+    use crate::sampling::Rng;
+    use crate::scene::Scene;
+    use crate::ALBEDO;
+
+    fn material() -> Material {
+        Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive)
+    }
+
+    fn random_point(rng: &mut Rng, size: f64) -> Point {
+        Point {
+            x: (rng.next_f64() - 0.5) * size,
+            y: (rng.next_f64() - 0.5) * size,
+            z: (rng.next_f64() - 0.5) * size,
+        }
+    }
+
+    fn random_direction(rng: &mut Rng) -> Vector3 {
+        loop {
+            let v: Vector3 = random_point(rng, 2.0).into();
+            if v.magnitude() > 1e-3 && v.magnitude() <= 1.0 {
+                return v.normalize();
+            }
+        }
+    }
+
+    fn random_triangle(rng: &mut Rng, center: Point) -> [Point; 3] {
+        [0, 1, 2].map(|_| center + random_point(rng, 2.0))
+    }
+
+    fn random_scene(rng: &mut Rng) -> Scene {
+        let mut objects = Vec::new();
+        for i in 0..200 {
+            let center = random_point(rng, 20.0);
+            objects.push(match i % 4 {
+                0 => Object::Sphere(Sphere { center, radius: 0.1 + rng.next_f64(), material: material() }),
+                1 => Object::Triangle(Triangle {
+                    vertices: random_triangle(rng, center),
+                    normals: None,
+                    texture_coords: None,
+                    material: material(),
+                }),
+                2 => {
+                    let vertices: Vec<Point> = (0..12).map(|_| center + random_point(rng, 3.0)).collect();
+                    let faces = (0..10)
+                        .map(|f| Face { vertices: [f, f + 1, f + 2], normals: None, texture_coords: None })
+                        .collect();
+                    Object::Mesh(Mesh::new(vertices, vec![], vec![], faces, material()))
+                }
+                _ if i % 40 == 3 => {
+                    Object::Plane(Plane { origin: center, normal: random_direction(rng), material: material() })
+                }
+                _ => Object::Sphere(Sphere { center, radius: 0.5, material: material() }),
+            });
+        }
+        // exact duplicates tie, the first one has to win
+        objects.push(Object::Sphere(Sphere { center: Point::zero(), radius: 1.0, material: material() }));
+        objects.push(Object::Sphere(Sphere { center: Point::zero(), radius: 1.0, material: material() }));
+
+        Scene { objects, ..Scene::empty() }
+    }
+
+    /// Nearest hit over every object, ties going to the first one
+    fn brute_force(scene: &Scene, ray: &Ray) -> Option<(usize, f64, usize)> {
+        scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter_map(|(i, object)| object.intersect(ray).map(|(distance, face)| (i, distance, face)))
+            .filter(|(_, distance, _)| !distance.is_nan())
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)))
+    }
+
+    #[test]
+    fn trace_matches_brute_force() {
+        let mut rng = Rng::new(0x5eed, 3);
+        let scene = random_scene(&mut rng);
+        let mut hits = 0;
+        for _ in 0..20_000 {
+            let ray = Ray::new(random_point(&mut rng, 30.0), random_direction(&mut rng));
+            let expected = brute_force(&scene, &ray);
+            let traced = scene.trace(&ray).map(|hit| {
+                let index = scene.objects.iter().position(|object| std::ptr::eq(object, hit.object)).unwrap();
+                (index, hit.distance, hit.face)
+            });
+            assert_eq!(traced.map(|(i, _, face)| (i, face)), expected.map(|(i, _, face)| (i, face)));
+            if let (Some((_, distance, _)), Some((_, expected, _))) = (traced, expected) {
+                assert_eq!(distance, expected);
+                let hit_point = |distance: f64| ray.origin + (ray.direction * distance).into();
+                let (a, b) = (hit_point(distance), hit_point(expected));
+                assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+                hits += 1;
+            }
+        }
+        // the rays have to exercise the hierarchy
+        assert!(hits > 5_000, "only {} hits", hits);
+    }
+
+    #[test]
+    fn union_ignores_empty_boxes() {
+        let unit = Aabb { min: Point::zero(), max: Point { x: 1.0, y: 1.0, z: 1.0 } };
+        for union in [unit.union(&Aabb::empty()), Aabb::empty().union(&unit)] {
+            assert_eq!((union.min.x, union.min.y, union.min.z), (0.0, 0.0, 0.0));
+            assert_eq!((union.max.x, union.max.y, union.max.z), (1.0, 1.0, 1.0));
+        }
+        assert_eq!(unit.union(&Aabb::empty()).surface_area(), 6.0);
+        assert_eq!(Aabb::empty().union(&Aabb::empty()).surface_area(), 0.0);
+    }
+
+    #[test]
+    fn split_next_to_empty_bins_is_finite() {
+        // one primitive at 0, one at 50 and a cluster at 100, with empty bins in between:
+        // the cheapest split keeps the cluster on its own
+        let bounds: Vec<Aabb> = (0..16)
+            .map(|i| {
+                let x = match i {
+                    0 => 0.0,
+                    1 => 50.0,
+                    i => 100.0 + i as f64 * 0.01,
+                };
+                Aabb { min: Point { x, y: 0.0, z: 0.0 }, max: Point { x: x + 0.01, y: 0.01, z: 0.01 } }
+            })
+            .collect();
+        let bvh = Bvh::build(&bounds);
+        let NodeKind::Interior { left, right } = bvh.nodes[0].kind else {
+            panic!("the root should be split");
+        };
+        assert!([left, right].iter().any(|&child| bvh.nodes[child].bounds.min.x >= 100.0));
+    }
+}
@@ -0,0 +1,42 @@
+use vector3::Vector3;
+
+use crate::point::Point;
+
+/// Pinhole camera, made with `Camera::new`
+pub struct Camera {
+    pub position: Point,
+    pub look_at: Point,
+    pub up: Vector3,
+    /// Vertical field of view in degrees
+    pub fov: f64,
+    /// Orthonormal camera basis: (right, up, forward), shared by every ray
+    basis: (Vector3, Vector3, Vector3),
+}
+
+impl Camera {
+    pub fn new(position: Point, look_at: Point, up: Vector3, fov: f64) -> Camera {
+        let forward = Vector3::from(look_at - position).normalize();
+        let mut right = forward.cross(&up);
+        if right.magnitude() < 1e-9 {
+            // `up` is parallel to the view direction, any perpendicular axis will do
+            right = forward.cross(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+            if right.magnitude() < 1e-9 {
+                right = forward.cross(&Vector3 { x: 0.0, y: 0.0, z: 1.0 });
+            }
+        }
+        let right = right.normalize();
+        let basis = (right, right.cross(&forward), forward);
+        Camera { position, look_at, up, fov, basis }
+    }
+
+    /// Direction of the ray going through the sensor point,
+    /// sensor coordinates are in [-1, 1] with y pointing up
+    pub fn direction(&self, sensor_x: f64, sensor_y: f64, aspect_ratio: f64) -> Vector3 {
+        let (right, up, forward) = self.basis;
+        let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
+        (forward
+            + right * (sensor_x * fov_adjustment * aspect_ratio)
+            + up * (sensor_y * fov_adjustment))
+            .normalize()
+    }
+}
@@ -1,17 +1,43 @@
 use image::Rgba;
 use std::ops::{Add, AddAssign, Mul};
 
-// TODO: implement better Gamma Correction
+// sRGB transfer functions
 // http://blog.johnnovak.net/2016/09/21/what-every-coder-should-know-about-gamma/
+// https://en.wikipedia.org/wiki/SRGB#Transformation
+
+/// Linear value in [0, 1] to its sRGB encoding
+pub fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
 
-const GAMMA: f32 = 2.2;
-
-fn gamma_encode(linear: f32) -> f32 {
-    linear.powf(1.0 / GAMMA)
+/// sRGB encoded value in [0, 1] to linear
+pub fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
 }
 
-fn gamma_decode(encoded: f32) -> f32 {
-    encoded.powf(GAMMA)
+/// Fraction of light left after `distance` through a medium with the `absorption` coefficient per channel,
+/// Beer–Lambert law https://en.wikipedia.org/wiki/Beer%E2%80%93Lambert_law
+pub fn beer_lambert(absorption: Color, distance: f64) -> Color {
+    let transmittance = |coefficient: f32| {
+        if coefficient > 0.0 {
+            (-coefficient as f64 * distance).exp() as f32
+        } else {
+            1.0
+        }
+    };
+    Color {
+        red: transmittance(absorption.red),
+        green: transmittance(absorption.green),
+        blue: transmittance(absorption.blue),
+    }
 }
 
 const MAX: f32 = 255.0;
@@ -26,28 +52,30 @@ pub struct Color {
 
 // Implementations for Color
 impl Color {
+    /// Quantizes the clamped color to 8-bit sRGB
     pub fn to_rgba(&self) -> Rgba<u8> {
-        return Rgba([
-            (MAX * gamma_encode(self.red)) as u8,
-            (MAX * gamma_encode(self.green)) as u8,
-            (MAX * gamma_encode(self.blue)) as u8,
-            MAX as u8,
-        ]);
+        let encode = |linear: f32| (MAX * srgb_encode(linear.clamp(0.0, 1.0))).round() as u8;
+        Rgba([encode(self.red), encode(self.green), encode(self.blue), MAX as u8])
     }
 
     pub fn from_rgba(rgba: Rgba<u8>) -> Color {
         Color {
-            red: gamma_decode(rgba.0[0] as f32 / MAX),
-            green: gamma_decode(rgba.0[1] as f32 / MAX),
-            blue: gamma_decode(rgba.0[2] as f32 / MAX),
+            red: srgb_decode(rgba.0[0] as f32 / MAX),
+            green: srgb_decode(rgba.0[1] as f32 / MAX),
+            blue: srgb_decode(rgba.0[2] as f32 / MAX),
         }
     }
 
+    /// Relative luminance of the linear color (Rec. 709 primaries)
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     pub fn clamp(&self) -> Color {
         Self {
-            red: self.red.max(0.0).min(1.0),
-            green: self.green.max(0.0).min(1.0),
-            blue: self.blue.max(0.0).min(1.0),
+            red: self.red.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
         }
     }
 }
@@ -106,3 +134,33 @@ impl Color {
     pub const DARK_BLUE: Color = Color { red: 0.4, green: 0.4, blue: 0.8, };
     pub const LIGHT_BLUE: Color = Color { red: 0.6, green: 0.9, blue: 1.0, };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip_is_exact_in_8_bits() {
+        for value in 0..=255u8 {
+            let encoded = value as f32 / 255.0;
+            let round_trip = srgb_encode(srgb_decode(encoded));
+            assert!((round_trip - encoded).abs() < 1e-6, "{}: {}", value, round_trip * 255.0);
+            assert_eq!((round_trip * 255.0).round() as u8, value);
+        }
+    }
+
+    #[test]
+    fn srgb_breakpoints() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert_eq!(srgb_decode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_decode(1.0) - 1.0).abs() < 1e-6);
+        // linear segment up to the breakpoints, the two pieces meet there
+        assert!((srgb_encode(0.001) - 0.01292).abs() < 1e-7);
+        assert!((srgb_encode(0.0031308) - 0.04045).abs() < 1e-5);
+        assert!((srgb_encode(0.0031309) - srgb_encode(0.0031308)).abs() < 1e-5);
+        assert!((srgb_decode(0.04045) - 0.0031308).abs() < 1e-6);
+        assert!((srgb_decode(0.04046) - srgb_decode(0.04045)).abs() < 1e-6);
+        assert!((srgb_encode(0.5) - 0.735_356_6).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,153 @@
+use std::f64::consts::PI;
+
+use image::{ColorType, DynamicImage};
+use vector3::Vector3;
+
+use crate::color::{srgb_decode, Color};
+use crate::sampling::Distribution2D;
+
+/// Light coming from infinitely far away, seen by rays that miss every object
+pub enum Environment {
+    Color(Color),
+    /// Blend between `bottom` straight down and `top` straight up, along the y axis
+    Gradient { bottom: Color, top: Color },
+    Map(EnvironmentMap),
+}
+
+/// Direction sampled towards the environment
+pub struct EnvironmentSample {
+    pub direction: Vector3,
+    pub radiance: Color,
+    /// Density of the direction per unit solid angle
+    pub pdf: f64,
+}
+
+impl Environment {
+    /// Radiance arriving along `-direction`, i.e. seen when looking towards `direction`
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        match self {
+            Environment::Color(color) => *color,
+            Environment::Gradient { bottom, top } => {
+                let t = ((direction.y + 1.0) / 2.0).clamp(0.0, 1.0) as f32;
+                *bottom * (1.0 - t) + *top * t
+            }
+            Environment::Map(map) => map.radiance(direction),
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        match self {
+            Environment::Color(color) => color.red <= 0.0 && color.green <= 0.0 && color.blue <= 0.0,
+            _ => false,
+        }
+    }
+
+    /// Picks a direction from the unit square sample (u, v).
+    /// Maps are importance sampled by luminance, other environments uniformly over the sphere.
+    pub fn sample(&self, (u, v): (f64, f64)) -> Option<EnvironmentSample> {
+        let (direction, pdf) = match self {
+            Environment::Map(map) => map.sample_direction(u, v)?,
+            _ => {
+                let y = 1.0 - 2.0 * v;
+                let r = (1.0 - y * y).max(0.0).sqrt();
+                let phi = 2.0 * PI * u;
+                (Vector3 { x: r * phi.cos(), y, z: r * phi.sin() }, 1.0 / (4.0 * PI))
+            }
+        };
+        Some(EnvironmentSample { radiance: self.radiance(&direction), direction, pdf })
+    }
+
+    /// Density of `sample` choosing the direction
+    pub fn pdf(&self, direction: &Vector3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+/// Equirectangular (latitude-longitude) radiance map, y is up
+/// and the center of the image looks towards -z
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Color>,
+    pub path: String,
+    /// Radiance multiplier
+    pub intensity: f32,
+    /// Rotation around the y axis in degrees
+    pub rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Float images (.hdr, .exr) are taken as linear radiance, 8 and 16-bit ones as sRGB
+    pub fn new(image: &DynamicImage, path: String, intensity: f32, rotation: f64) -> EnvironmentMap {
+        let linear = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let image = image.to_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels: Vec<Color> = image
+            .pixels()
+            .map(|pixel| {
+                let [red, green, blue] = if linear { pixel.0 } else { pixel.0.map(srgb_decode) };
+                Color { red, green, blue }
+            })
+            .collect();
+
+        // rows near the poles cover less solid angle
+        let weights: Vec<f64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let theta = PI * ((i as u32 / width) as f64 + 0.5) / height as f64;
+                color.luminance() as f64 * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width as usize, height as usize);
+
+        EnvironmentMap { width, height, pixels, path, intensity, rotation, distribution }
+    }
+
+    /// Image coordinates in [0, 1) of the direction
+    fn coords(&self, direction: &Vector3) -> (f64, f64) {
+        let phi = direction.x.atan2(-direction.z) - self.rotation.to_radians();
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vector3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation.to_radians();
+        let theta = v * PI;
+        Vector3 {
+            x: theta.sin() * phi.sin(),
+            y: theta.cos(),
+            z: -theta.sin() * phi.cos(),
+        }
+    }
+
+    fn radiance(&self, direction: &Vector3) -> Color {
+        let (u, v) = self.coords(direction);
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+        self.pixels[(y * self.width + x) as usize] * self.intensity
+    }
+
+    fn sample_direction(&self, u: f64, v: f64) -> Option<(Vector3, f64)> {
+        let ((x, y), pdf) = self.distribution.sample((u, v));
+        let sin_theta = (y * PI).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        // the image covers 2 pi by pi radians, d(omega) = sin(theta) d(theta) d(phi)
+        Some((self.direction(x, y), pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, direction: &Vector3) -> f64 {
+        let (u, v) = self.coords(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Pixel reconstruction filters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl FilterKind {
+    pub const NAMES: &str = "`box`, `tent`, `gaussian`, `mitchell`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+        }
+    }
+
+    /// Radius used when none is given, in pixels
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            _ => Err(format!("unknown filter `{}`, expected one of {}", name, FilterKind::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Separable pixel filter: a sample at offset (dx, dy) from a pixel center
+/// contributes to it with the weight f(dx) * f(dy)
+#[derive(Clone, Copy)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Extent of the filter in pixels
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter { kind, radius: kind.default_radius() }
+    }
+
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, offset: f64) -> f64 {
+        let x = offset.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                // standard deviation of a third of the radius, shifted to reach zero at the edge
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(self.radius)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius, 1.0 / 3.0, 1.0 / 3.0),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+/// Mitchell–Netravali cubic on [0, 2]
+/// https://en.wikipedia.org/wiki/Mitchell%E2%80%93Netravali_filters
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x.powi(2) + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    value / 6.0
+}
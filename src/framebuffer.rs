@@ -0,0 +1,109 @@
+use image::{DynamicImage, GenericImage};
+
+use crate::color::Color;
+use crate::tone_mapping::ToneMapping;
+
+/// Floating point image accumulating filter-weighted samples.
+/// Pixel (x, y) of the buffer is pixel (origin_x + x, origin_y + y) of the frame,
+/// so buffers covering only a part of the frame can be merged into a larger one.
+pub struct Framebuffer {
+    pub origin_x: i64,
+    pub origin_y: i64,
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Color>,
+    weights: Vec<f32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer::with_origin(0, 0, width, height)
+    }
+
+    pub fn with_origin(origin_x: i64, origin_y: i64, width: u32, height: u32) -> Framebuffer {
+        let size = (width * height) as usize;
+        Framebuffer {
+            origin_x,
+            origin_y,
+            width,
+            height,
+            sums: vec![Color::BLACK; size],
+            weights: vec![0.0; size],
+        }
+    }
+
+    /// Index of the frame pixel (x, y), if the buffer covers it
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let (x, y) = (x - self.origin_x, y - self.origin_y);
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+        Some((y * self.width as i64 + x) as usize)
+    }
+
+    /// Adds a sample to the frame pixel (x, y), ignored outside of the buffer
+    pub fn add(&mut self, x: i64, y: i64, color: Color, weight: f32) {
+        if let Some(i) = self.index(x, y) {
+            self.sums[i] += color * weight;
+            self.weights[i] += weight;
+        }
+    }
+
+    /// Adds the samples of another buffer, the parts outside of this one are dropped
+    pub fn merge(&mut self, other: &Framebuffer) {
+        for y in 0..other.height as i64 {
+            for x in 0..other.width as i64 {
+                let i = (y * other.width as i64 + x) as usize;
+                if let Some(j) = self.index(other.origin_x + x, other.origin_y + y) {
+                    self.sums[j] += other.sums[i];
+                    self.weights[j] += other.weights[i];
+                }
+            }
+        }
+    }
+
+    /// Normalized color of the buffer pixel (x, y).
+    /// Negative filter lobes can cancel the weights out, such pixels count as not covered
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = (y * self.width + x) as usize;
+        if self.weights[i] <= f32::EPSILON {
+            Color::BLACK
+        } else {
+            self.sums[i] * (1.0 / self.weights[i])
+        }
+    }
+
+    /// Tone maps the buffer into an 8-bit sRGB image
+    pub fn to_image(&self, tone_mapping: &ToneMapping) -> DynamicImage {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.put_pixel(x, y, tone_mapping.apply(self.pixel(x, y)).to_rgba());
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_weights_are_not_coverage() {
+        let mut framebuffer = Framebuffer::new(3, 1);
+        let color = Color { red: 1.0, green: 0.5, blue: 0.25 };
+        framebuffer.add(0, 0, color, 0.5);
+        // a negative lobe cancelling a positive one, then a pixel left with only a negative one
+        framebuffer.add(1, 0, color, 0.25);
+        framebuffer.add(1, 0, color, -0.25);
+        framebuffer.add(2, 0, color, -0.1);
+
+        let covered = framebuffer.pixel(0, 0);
+        assert_eq!([covered.red, covered.green, covered.blue], [1.0, 0.5, 0.25]);
+        for x in 1..3 {
+            let pixel = framebuffer.pixel(x, 0);
+            assert_eq!([pixel.red, pixel.green, pixel.blue], [0.0; 3], "pixel {}", x);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors of loading IESNA LM-63 photometric files
+#[derive(Debug)]
+pub enum IesError {
+    Io { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, message: String },
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            IesError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for IesError {}
+
+/// Angular distribution of a luminaire's light, type C photometry:
+/// vertical angles go from the nadir (0°) to the zenith (180°), horizontal ones around the nadir
+pub struct IesProfile {
+    /// Path the profile was loaded from
+    pub path: String,
+    /// In degrees, ascending
+    vertical: Vec<f64>,
+    /// In degrees, ascending
+    horizontal: Vec<f64>,
+    /// Candela values of each horizontal angle over the vertical ones, scaled so the brightest is 1
+    candela: Vec<f32>,
+}
+
+/// Loads an LM-63 file (1986, 1991, 1995 or 2002 format), lamp tilt data is skipped
+pub fn load_ies(path: &Path) -> Result<IesProfile, IesError> {
+    let source = fs::read_to_string(path).map_err(|error| IesError::Io { path: path.to_path_buf(), error })?;
+    parse_ies(&source, path)
+}
+
+/// Parses the LM-63 `source` of the file at `path`
+fn parse_ies(source: &str, path: &Path) -> Result<IesProfile, IesError> {
+    let error = |message: &str| IesError::Parse { path: path.to_path_buf(), message: message.to_string() };
+
+    // keyword lines come before the tilt line, the photometric data after it
+    let mut lines = source.lines();
+    let tilt = lines
+        .by_ref()
+        .find_map(|line| line.trim().strip_prefix("TILT="))
+        .ok_or_else(|| error("missing `TILT=` line"))?
+        .trim();
+    let mut numbers = lines.flat_map(|line| line.split([' ', '\t', ','])).filter(|token| !token.is_empty());
+    let mut next = || -> Result<f64, IesError> {
+        let token = numbers.next().ok_or_else(|| error("unexpected end of the photometric data"))?;
+        token.parse().map_err(|_| error(&format!("expected a number, got `{}`", token)))
+    };
+    let count = |value: f64, what: &str| -> Result<usize, IesError> {
+        if value < 1.0 || value.fract() != 0.0 {
+            return Err(error(&format!("expected a positive whole number of {}", what)));
+        }
+        Ok(value as usize)
+    };
+
+    if tilt == "INCLUDE" {
+        // lamp-to-luminaire geometry, then pairs of tilt angles and factors
+        next()?;
+        let pairs = count(next()?, "tilt angles")?;
+        for _ in 0..2 * pairs {
+            next()?;
+        }
+    }
+
+    // lamp count, lumens per lamp, candela multiplier
+    for _ in 0..3 {
+        next()?;
+    }
+    let vertical_count = count(next()?, "vertical angles")?;
+    let horizontal_count = count(next()?, "horizontal angles")?;
+    if next()? != 1.0 {
+        return Err(error("only type C photometry is supported"));
+    }
+    // units, luminous opening width, length and height, ballast factor, file generation type, input watts
+    for _ in 0..7 {
+        next()?;
+    }
+    let mut angles = |count| -> Result<Vec<f64>, IesError> {
+        let angles = (0..count).map(|_| next()).collect::<Result<Vec<_>, _>>()?;
+        if angles.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(error("angles must be ascending"));
+        }
+        Ok(angles)
+    };
+    let vertical = angles(vertical_count)?;
+    let horizontal = angles(horizontal_count)?;
+    let candela = (0..vertical_count * horizontal_count)
+        .map(|_| next().map(|value| value as f32))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let max = candela.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Err(error("the luminaire emits no light"));
+    }
+    Ok(IesProfile {
+        path: path.display().to_string(),
+        vertical,
+        horizontal,
+        candela: candela.iter().map(|value| value / max).collect(),
+    })
+}
+
+impl IesProfile {
+    /// Relative intensity `vertical` degrees from the nadir and `horizontal` degrees around it
+    pub fn intensity(&self, vertical: f64, horizontal: f64) -> f32 {
+        let horizontal = self.symmetric(horizontal.rem_euclid(360.0));
+        let (i, s) = lerp_position(&self.horizontal, horizontal, self.wraps());
+        let (j, t) = lerp_position(&self.vertical, vertical, false);
+        let (Some((i0, i1)), Some((j0, j1))) = (i, j) else {
+            return 0.0;
+        };
+        let at = |i: usize, j: usize| self.candela[i * self.vertical.len() + j];
+        let (s, t) = (s as f32, t as f32);
+        (at(i0, j0) * (1.0 - t) + at(i0, j1) * t) * (1.0 - s) + (at(i1, j0) * (1.0 - t) + at(i1, j1) * t) * s
+    }
+
+    /// Maps a horizontal angle in [0, 360) into the range the file covers, by the symmetry the range implies
+    fn symmetric(&self, angle: f64) -> f64 {
+        let (first, last) = (self.horizontal[0], self.horizontal[self.horizontal.len() - 1]);
+        match (first, last) {
+            // the same in every direction
+            _ if first == last => first,
+            // symmetric about both the 0-180° and the 90-270° planes
+            (0.0, 90.0) => 90.0 - (angle % 180.0 - 90.0).abs(),
+            // symmetric about the 0-180° plane
+            (0.0, 180.0) => 180.0 - (angle - 180.0).abs(),
+            // symmetric about the 90-270° plane
+            (90.0, 270.0) if !(90.0..=270.0).contains(&angle) => (180.0 - angle).rem_euclid(360.0),
+            _ => angle,
+        }
+    }
+
+    /// Whether the horizontal angles go all the way around, so the last one is followed by the first
+    fn wraps(&self) -> bool {
+        self.horizontal.len() > 1 && self.horizontal[0] == 0.0 && self.horizontal[self.horizontal.len() - 1] > 180.0
+    }
+}
+
+/// The pair of `angles` around `angle` and how far it is between them,
+/// `None` outside the angles unless they `wrap` around the full circle
+fn lerp_position(angles: &[f64], angle: f64, wrap: bool) -> (Option<(usize, usize)>, f64) {
+    let last = angles.len() - 1;
+    let i = angles.partition_point(|a| *a <= angle);
+    if i == 0 {
+        return (None, 0.0);
+    }
+    if i <= last {
+        let t = (angle - angles[i - 1]) / (angles[i] - angles[i - 1]);
+        return (Some((i - 1, i)), t);
+    }
+    if angle == angles[last] {
+        (Some((last, last)), 0.0)
+    } else if wrap && angle < 360.0 {
+        (Some((last, 0)), (angle - angles[last]) / (360.0 - angles[last]))
+    } else {
+        (None, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LM-63 file with type C photometry, `candela` holds the vertical values of each horizontal angle in turn
+    fn profile(tilt: &str, vertical: &[f64], horizontal: &[f64], candela: &[f64]) -> String {
+        let list = |values: &[f64]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" ");
+        format!(
+            "IESNA:LM-63-2002\n[TEST] inline\nTILT={}\n1 1000 1 {} {} 1 2 0 0 0\n1 1 100\n{}\n{}\n{}\n",
+            tilt,
+            vertical.len(),
+            horizontal.len(),
+            list(vertical),
+            list(horizontal),
+            list(candela)
+        )
+    }
+
+    fn parse(source: &str) -> Result<IesProfile, IesError> {
+        parse_ies(source, Path::new("test.ies"))
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn tilt_data_is_skipped() {
+        let none = parse(&profile("NONE", &[0.0, 90.0], &[0.0], &[100.0, 50.0])).unwrap();
+        let include = profile("INCLUDE", &[0.0, 90.0], &[0.0], &[100.0, 50.0])
+            .replace("TILT=INCLUDE\n", "TILT=INCLUDE\n1\n3\n0 45 90\n1 0.9 0.8\n");
+        let include = parse(&include).unwrap();
+        for vertical in [0.0, 30.0, 90.0] {
+            assert_close(include.intensity(vertical, 0.0), none.intensity(vertical, 0.0));
+        }
+        assert_close(none.intensity(0.0, 0.0), 1.0);
+        assert_close(none.intensity(45.0, 0.0), 0.75);
+        // beyond the last vertical angle
+        assert_close(none.intensity(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn one_horizontal_angle_is_the_same_all_around() {
+        let profile = parse(&profile("NONE", &[0.0, 90.0], &[0.0], &[100.0, 50.0])).unwrap();
+        for horizontal in [0.0, 45.0, 180.0, 300.0, -90.0] {
+            assert_close(profile.intensity(45.0, horizontal), 0.75);
+        }
+    }
+
+    #[test]
+    fn quadrant_symmetry() {
+        let profile = parse(&profile("NONE", &[0.0, 90.0], &[0.0, 90.0], &[100.0, 100.0, 100.0, 40.0])).unwrap();
+        let reference = profile.intensity(90.0, 30.0);
+        assert_close(reference, 0.8);
+        for mirrored in [150.0, 210.0, 330.0, -30.0] {
+            assert_close(profile.intensity(90.0, mirrored), reference);
+        }
+        assert_close(profile.intensity(90.0, 270.0), 0.4);
+    }
+
+    #[test]
+    fn bilateral_symmetry() {
+        let candela = [100.0, 100.0, 100.0, 70.0, 100.0, 40.0];
+        let profile = parse(&profile("NONE", &[0.0, 90.0], &[0.0, 90.0, 180.0], &candela)).unwrap();
+        assert_close(profile.intensity(90.0, 45.0), 0.85);
+        assert_close(profile.intensity(90.0, 315.0), 0.85);
+        assert_close(profile.intensity(90.0, 135.0), 0.55);
+        assert_close(profile.intensity(90.0, 225.0), 0.55);
+    }
+
+    #[test]
+    fn full_circle_wraps_around() {
+        let candela = [100.0, 100.0, 100.0, 80.0, 100.0, 60.0, 100.0, 20.0];
+        let profile = parse(&profile("NONE", &[0.0, 90.0], &[0.0, 90.0, 180.0, 270.0], &candela)).unwrap();
+        assert_close(profile.intensity(90.0, 45.0), 0.9);
+        assert_close(profile.intensity(90.0, 270.0), 0.2);
+        // between the last angle and the first one again
+        assert_close(profile.intensity(90.0, 315.0), 0.6);
+        assert_close(profile.intensity(90.0, -45.0), 0.6);
+        // no mirroring
+        assert_close(profile.intensity(90.0, 135.0), 0.7);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let valid = profile("NONE", &[0.0, 90.0], &[0.0], &[100.0, 50.0]);
+        let truncated = valid.trim_end().rsplit_once(' ').unwrap().0.to_string();
+        let cases = [
+            valid.replace("TILT=NONE", "NO TILT"),
+            truncated,
+            valid.replace("1000", "lots"),
+            // type A photometry
+            valid.replace("2 1 1", "2 1 2"),
+            valid.replace("0 90\n", "90 0\n"),
+            valid.replace("100 50", "0 0"),
+            valid.replace("1 1000 1 2 1", "1 1000 1 2.5 1"),
+            valid.replace("1 1000 1 2 1", "1 1000 1 0 1"),
+            valid.replace("TILT=NONE\n", "TILT=INCLUDE\n1\n"),
+            "TILT=NONE\n".to_string(),
+            String::new(),
+        ];
+        for source in &cases {
+            assert!(matches!(parse(source), Err(IesError::Parse { .. })), "accepted:\n{}", source);
+        }
+        assert!(matches!(load_ies(Path::new("missing.ies")), Err(IesError::Io { .. })));
+    }
+}
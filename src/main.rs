@@ -1,152 +1,166 @@
 // foreign crates
 use chrono::{Local, Timelike};
-use object::Material;
-use std::io::{self, BufRead};
-use vector3::Vector3;
+use clap::{Parser, Subcommand};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 // declaring domestic crates
+pub mod bsdf;
+pub mod bvh;
+pub mod camera;
 pub mod color;
+pub mod environment;
+pub mod filter;
+pub mod framebuffer;
+pub mod ies;
+pub mod medium;
+pub mod obj;
 pub mod object;
+pub mod output;
 pub mod point;
+pub mod procedural;
 pub mod rendering;
+pub mod sampling;
 pub mod scene;
+pub mod scene_file;
+pub mod spectrum;
+pub mod texture;
+pub mod tone_mapping;
 pub mod tracing;
 
 // domestic crates
-use crate::color::Color;
-use crate::object::{Object, Plane, Sphere, Surface};
-use crate::point::Point;
-use crate::scene::{DirectionalLight, Light, Scene, SphericalLight};
-use crate::tracing::render;
+use crate::environment::Environment;
+use crate::filter::{Filter, FilterKind};
+use crate::object::Object;
+use crate::output::{save_render, ExrPrecision};
+use crate::scene::{Light, Scene};
+use crate::scene_file::{load_scene, save_scene};
+use crate::tone_mapping::{ToneMapOperator, ToneMapping};
+use crate::tracing::{render, Integrator};
 
 // consts
 const SHADOW_BIAS: f64 = 1e-3;
 const ALBEDO: f32 = 0.8;
 
-/// Test render scene
-fn test_render_scene() {
-    let scene = Scene {
-        width: 1920,
-        height: 1080,
-        fov: 90.0,
-        objects: vec![
-            // Object::Sphere(Sphere {
-            //     center: Point {
-            //         x: 0.0,
-            //         y: 0.0,
-            //         z: -3.0,
-            //     },
-            //     radius: 1.0,
-            //     material: Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive),
-            // }),
-            Object::Sphere(Sphere {
-                center: Point {
-                    x: 4.0,
-                    y: 0.0,
-                    z: -5.0,
-                },
-                radius: 2.0,
-                material: Material::from_color(Color::LIGHT_BLUE, ALBEDO, Surface::Reflective { reflectivity: 0.8 }),
-            }),
-            Object::Sphere(Sphere {
-                center: Point {
-                    x: -3.0,
-                    y: 0.0,
-                    z: -7.0,
-                },
-                radius: 2.0,
-                material: Material::get_texture(Material::WOOD, 2.0, 0.0, Surface::Diffusive),
-            }),
-            Object::Sphere(Sphere {
-                center: Point {
-                    x: -5.0,
-                    y: 0.0,
-                    z: -4.0,
-                },
-                radius: 2.0,
-                material: Material::from_color(Color::LIGHT_BLUE, ALBEDO, Surface::Refractive { transparency: 1.0, index: 1.33 }),
-            }),
-            // Object::Sphere(Sphere {
-            //     center: Point {
-            //         x: -3.0,
-            //         y: 0.0,
-            //         z: -3.0,
-            //     },
-            //     radius: 1.0,
-            //     material: Material::from_color(Color::LIGHT_GREEN, ALBEDO, Surface::Diffusive),
-            // }),
-            // Object::Sphere(Sphere { // roflosphere
-            //     center: Point {
-            //         x: 0.0,
-            //         y: 0.0,
-            //         z: -101.0,
-            //     },
-            //     radius: 100.0,
-            //     material: Material::from_color(Color::LIGHT_GREEN, ALBEDO, Surface::Refractive { transparency: 1.0, index: 1.2 }),
-            // }),
-            Object::Plane(Plane {
-                // Floor
-                origin: Point {
-                    x: 0.0,
-                    y: 2.5,
-                    z: 0.0,
-                },
-                normal: Vector3 {
-                    x: 0.0,
-                    y: 1.0,
-                    z: 0.0,
-                }
-                .normalize(),
-                material: Material::get_texture(Material::CHECKERBOARD, 0.2, 0.0, Surface::Reflective { reflectivity: 0.4 }),
-            }),
-        ],
-        lights: vec![
-            Light::Directional(DirectionalLight {
-                direction: Vector3 {
-                    x: 5.0,
-                    y: 5.0,
-                    z: -5.0,
-                }
-                .normalize(),
-                color: Color::WHITE,
-                intensity: 2.5,
-            }),
-            Light::Directional(DirectionalLight {
-                direction: Vector3 {
-                    x: -2.0,
-                    y: 3.0,
-                    z: -5.0,
-                }
-                .normalize(),
-                color: Color::YELLOW,
-                intensity: 1.5,
-            }),
-            Light::Spherical(SphericalLight {
-                position: Point {
-                    x: 0.0,
-                    y: -1.0,
-                    z: -3.0,
-                },
-                color: Color::LIGHT_GREEN,
-                intensity: 200.0,
-            }),
-        ],
-        max_recursion_depth: 5,
-    };
-
-    // Getting image
-    let img = render(&scene);
-    assert_eq!(scene.width, img.width());
-    assert_eq!(scene.height, img.height());
+/// Toy raytracer
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    // Saving the image
-    let save = io::stdin().lock().lines().next().unwrap().unwrap();
+#[derive(Subcommand)]
+enum Command {
+    /// Render a scene description file into an image
+    Render {
+        /// Scene description (TOML)
+        scene: PathBuf,
+        /// Output path template, the format is taken from the extension.
+        /// `.exr`, `.hdr` and `.pfm` store linear radiance, other formats are tone mapped.
+        /// `{name}` expands to `<tag>=<date>=<time>`, `{scene}` to the scene file name
+        #[arg(short, long, default_value = "img/{name}={scene}.jpg")]
+        output: String,
+        /// Sample type of OpenEXR output: half or float
+        #[arg(long, default_value = "float")]
+        exr_precision: ExrPrecision,
+        /// Tag used by `{name}` in the output template
+        #[arg(long, default_value = "pic")]
+        tag: String,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Write a scene back out as a scene description, with the render settings overridden
+    /// and OBJ models inlined as meshes
+    Convert {
+        /// Scene description (TOML)
+        scene: PathBuf,
+        /// Path of the new scene description
+        output: PathBuf,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Print scene statistics
+    Info {
+        /// Scene description (TOML)
+        scene: PathBuf,
+    },
+}
 
-    if !save.is_empty() {
-        let path = "img/".to_owned() + &get_name("pic".to_string()) + "=" + save.as_str() + ".jpg";
+/// Render settings overriding the ones from the scene file
+#[derive(clap::Args)]
+struct RenderOptions {
+    /// Image width
+    #[arg(long)]
+    width: Option<u32>,
+    /// Image height
+    #[arg(long)]
+    height: Option<u32>,
+    /// Light transport algorithm: whitted or path
+    #[arg(long)]
+    integrator: Option<Integrator>,
+    /// Maximum number of bounces of a camera ray
+    #[arg(long)]
+    depth: Option<u32>,
+    /// Number of samples per pixel
+    #[arg(long)]
+    spp: Option<u32>,
+    /// Pixel reconstruction filter: box, tent, gaussian or mitchell
+    #[arg(long)]
+    filter: Option<FilterKind>,
+    /// Filter radius in pixels, defaults to the radius of the chosen filter
+    #[arg(long)]
+    filter_radius: Option<f64>,
+    /// Tone mapping operator: clamp, reinhard, extended_reinhard, aces or hable
+    #[arg(long)]
+    tone_mapping: Option<ToneMapOperator>,
+    /// Exposure adjustment in stops
+    #[arg(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+    /// White point of extended_reinhard and hable, defaults to the operator's one
+    #[arg(long)]
+    white_point: Option<f32>,
+    /// Number of rendering threads, 0 uses one thread per core
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Render with wavelengths instead of RGB, for dispersion
+    #[arg(long)]
+    spectral: bool,
+}
 
-        println!("image saved to: {}", path);
-        img.save(path).unwrap();
+impl RenderOptions {
+    fn apply(&self, scene: &mut Scene) -> Result<(), Box<dyn Error>> {
+        scene.width = self.width.unwrap_or(scene.width);
+        scene.height = self.height.unwrap_or(scene.height);
+        scene.integrator = self.integrator.unwrap_or(scene.integrator);
+        scene.max_recursion_depth = self.depth.unwrap_or(scene.max_recursion_depth);
+        scene.samples_per_pixel = self.spp.unwrap_or(scene.samples_per_pixel);
+        if let Some(kind) = self.filter {
+            scene.filter = Filter::new(kind);
+        }
+        scene.filter.radius = self.filter_radius.unwrap_or(scene.filter.radius);
+        if let Some(operator) = self.tone_mapping {
+            scene.tone_mapping = ToneMapping {
+                exposure: scene.tone_mapping.exposure,
+                ..ToneMapping::new(operator)
+            };
+        }
+        scene.tone_mapping.exposure = self.exposure.unwrap_or(scene.tone_mapping.exposure);
+        scene.tone_mapping.white_point = self.white_point.unwrap_or(scene.tone_mapping.white_point);
+        scene.threads = self.threads.unwrap_or(scene.threads);
+        scene.spectral |= self.spectral;
+        if scene.width == 0 || scene.height == 0 {
+            return Err("the image width and height must be positive".into());
+        }
+        if scene.filter.radius.is_nan() || scene.filter.radius <= 0.0 {
+            return Err("the filter radius must be positive".into());
+        }
+        if scene.tone_mapping.white_point.is_nan() || scene.tone_mapping.white_point <= 0.0 {
+            return Err("the white point must be positive".into());
+        }
+        Ok(())
     }
 }
 
@@ -154,18 +168,144 @@ fn test_render_scene() {
 fn get_name(tag: String) -> String {
     let time = Local::now();
     format!(
-        "{}={}={}",
+        "{}={}={:02}-{:02}-{:02}",
         tag,
         time.date_naive(),
-        format!(
-            "{:02}-{:02}-{:02}",
-            time.hour(),
-            time.minute(),
-            time.second()
-        )
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Expands `{name}` and `{scene}` in the output path template
+fn output_path(template: &str, tag: &str, scene_path: &Path) -> PathBuf {
+    let scene_name = scene_path.file_stem().unwrap_or_default().to_string_lossy();
+    PathBuf::from(
+        template
+            .replace("{name}", &get_name(tag.to_string()))
+            .replace("{scene}", &scene_name),
     )
 }
 
-fn main() {
-    test_render_scene();
+fn render_command(
+    scene_path: &Path,
+    output: &str,
+    tag: &str,
+    exr_precision: ExrPrecision,
+    options: &RenderOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut scene = load_scene(scene_path)?;
+    options.apply(&mut scene)?;
+
+    let start = Local::now();
+    let framebuffer = render(&scene);
+    println!(
+        "rendered {}x{} in {:.2}s",
+        framebuffer.width,
+        framebuffer.height,
+        (Local::now() - start).num_milliseconds() as f64 / 1000.0
+    );
+
+    let path = output_path(output, tag, scene_path);
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    save_render(&framebuffer, &path, &scene.tone_mapping, exr_precision)?;
+    println!("image saved to: {}", path.display());
+    Ok(())
+}
+
+fn convert_command(scene_path: &Path, output: &Path, options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let mut scene = load_scene(scene_path)?;
+    options.apply(&mut scene)?;
+    save_scene(&scene, output)?;
+    println!("scene saved to: {}", output.display());
+    Ok(())
+}
+
+fn info_command(scene_path: &Path) -> Result<(), Box<dyn Error>> {
+    let scene = load_scene(scene_path)?;
+    print_info(scene_path, &scene);
+    Ok(())
+}
+
+fn print_info(scene_path: &Path, scene: &Scene) {
+    let count = |matches: fn(&Object) -> bool| scene.objects.iter().filter(|o| matches(o)).count();
+    let faces: usize = scene
+        .objects
+        .iter()
+        .map(|object| match object {
+            Object::Mesh(mesh) => mesh.faces.len(),
+            _ => 0,
+        })
+        .sum();
+    let lights = |matches: fn(&Light) -> bool| scene.lights.iter().filter(|l| matches(l)).count();
+
+    let camera = &scene.camera;
+    println!("scene: {}", scene_path.display());
+    println!("resolution: {}x{}", scene.width, scene.height);
+    println!("integrator: {}", scene.integrator);
+    println!("samples per pixel: {}", scene.samples_per_pixel);
+    println!("spectral: {}", scene.spectral);
+    println!("filter: {} (radius {})", scene.filter.kind, scene.filter.radius);
+    println!(
+        "tone mapping: {} (exposure {}, white point {})",
+        scene.tone_mapping.operator, scene.tone_mapping.exposure, scene.tone_mapping.white_point
+    );
+    println!("max recursion depth: {}", scene.max_recursion_depth);
+    println!(
+        "camera: position ({}, {}, {}), look at ({}, {}, {}), fov {}°",
+        camera.position.x,
+        camera.position.y,
+        camera.position.z,
+        camera.look_at.x,
+        camera.look_at.y,
+        camera.look_at.z,
+        camera.fov
+    );
+    println!(
+        "objects: {} ({} spheres, {} planes, {} triangles, {} meshes with {} faces)",
+        scene.objects.len(),
+        count(|o| matches!(o, Object::Sphere(_))),
+        count(|o| matches!(o, Object::Plane(_))),
+        count(|o| matches!(o, Object::Triangle(_))),
+        count(|o| matches!(o, Object::Mesh(_))),
+        faces
+    );
+    println!(
+        "environment: {}",
+        match &scene.environment {
+            Environment::Color(c) => format!("color ({}, {}, {})", c.red, c.green, c.blue),
+            Environment::Gradient { .. } => "gradient".to_string(),
+            Environment::Map(map) => format!("map {} ({}x{})", map.path, map.width, map.height),
+        }
+    );
+    println!(
+        "lights: {} ({} directional, {} spherical, {} rectangle, {} disk, {} spot)",
+        scene.lights.len(),
+        lights(|l| matches!(l, Light::Directional(_))),
+        lights(|l| matches!(l, Light::Spherical(_))),
+        lights(|l| matches!(l, Light::Rectangle(_))),
+        lights(|l| matches!(l, Light::Disk(_))),
+        lights(|l| matches!(l, Light::Spot(_)))
+    );
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Command::Render { scene, output, tag, exr_precision, options } => {
+            render_command(scene, output, tag, *exr_precision, options)
+        }
+        Command::Convert { scene, output, options } => convert_command(scene, output, options),
+        Command::Info { scene } => info_command(scene),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
 }
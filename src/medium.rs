@@ -0,0 +1,181 @@
+use std::ptr;
+
+use crate::color::Color;
+use crate::object::Object;
+use crate::spectrum::{self, Wavelengths};
+
+/// Dielectric objects a ray can be inside of at once
+const MAX_NESTING: usize = 8;
+
+/// Dielectric objects a ray is inside of, in the order it entered them.
+/// Where objects overlap the volume belongs to the one with the highest priority (the last entered one among equals),
+/// so a liquid can overlap the walls of its glass instead of having to match them exactly.
+/// Surfaces of objects inside a higher priority medium aren't there for the ray, it passes through them unchanged.
+/// https://doi.org/10.1080/10867651.2002.10487555
+#[derive(Clone, Copy, Default)]
+pub struct MediumStack<'a> {
+    objects: [Option<&'a Object>; MAX_NESTING],
+    len: usize,
+}
+
+impl<'a> MediumStack<'a> {
+    /// Object whose medium the ray travels in, `None` for air
+    pub fn current(&self) -> Option<&'a Object> {
+        self.objects[..self.len]
+            .iter()
+            .flatten()
+            .copied()
+            .max_by_key(|object| object.material().priority)
+    }
+
+    /// Index of refraction of the current medium at the hero wavelength
+    pub fn index(&self, wavelengths: Option<Wavelengths>) -> f32 {
+        self.current().and_then(|object| object.surface_type().index(wavelengths)).unwrap_or(1.0)
+    }
+
+    /// Absorption coefficient of the current medium at the wavelengths
+    pub fn absorption(&self, wavelengths: Option<Wavelengths>) -> Color {
+        self.current().map_or(Color::BLACK, |object| spectrum::at(object.surface_type().absorption(), wavelengths))
+    }
+
+    /// Index of refraction of the medium around `object`, on the other side of its surface
+    pub fn outside_index(&self, object: &Object, wavelengths: Option<Wavelengths>) -> f32 {
+        self.left(object).index(wavelengths)
+    }
+
+    /// Whether the object's surface separates two media for a ray `entering` or leaving it
+    pub fn is_interface(&self, object: &Object, entering: bool) -> bool {
+        match self.current() {
+            None => true,
+            Some(current) if entering => object.material().priority >= current.material().priority,
+            // objects the ray wasn't known to be in, like ones around the camera, count as the current medium
+            Some(current) => ptr::eq(current, object) || !self.contains(object),
+        }
+    }
+
+    /// The media after entering `object`, unchanged when nested too deep
+    pub fn entered(&self, object: &'a Object) -> MediumStack<'a> {
+        let mut media = *self;
+        if media.len < MAX_NESTING {
+            media.objects[media.len] = Some(object);
+            media.len += 1;
+        }
+        media
+    }
+
+    /// The media after leaving `object`
+    pub fn left(&self, object: &Object) -> MediumStack<'a> {
+        let mut media = *self;
+        if let Some(i) = self.position(object) {
+            media.objects.copy_within(i + 1..self.len, i);
+            media.len -= 1;
+            media.objects[media.len] = None;
+        }
+        media
+    }
+
+    fn contains(&self, object: &Object) -> bool {
+        self.position(object).is_some()
+    }
+
+    /// Position of the last time the ray entered `object`
+    fn position(&self, object: &Object) -> Option<usize> {
+        self.objects[..self.len]
+            .iter()
+            .rposition(|entered| entered.is_some_and(|entered| ptr::eq(entered, object)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Material, Sphere, Surface};
+    use crate::point::Point;
+    use crate::spectrum::Ior;
+    use crate::ALBEDO;
+
+    fn dielectric(index: f32, priority: u32) -> Object {
+        let surface = Surface::Refractive { transparency: 1.0, index: Ior::Constant(index), absorption: Color::BLACK };
+        Object::Sphere(Sphere {
+            center: Point::zero(),
+            radius: 1.0,
+            material: Material { priority, ..Material::from_color(Color::WHITE, ALBEDO, surface) },
+        })
+    }
+
+    fn is(object: Option<&Object>, expected: &Object) -> bool {
+        object.is_some_and(|object| ptr::eq(object, expected))
+    }
+
+    #[test]
+    fn highest_priority_wins() {
+        let (glass, water, ice) = (dielectric(1.5, 1), dielectric(1.33, 2), dielectric(1.31, 0));
+        let media = MediumStack::default();
+        assert!(media.current().is_none());
+        assert_eq!(media.index(None), 1.0);
+
+        let media = media.entered(&glass).entered(&water).entered(&ice);
+        assert!(is(media.current(), &water));
+        assert_eq!(media.index(None), 1.33);
+
+        // the last entered one among equals
+        let (first, second) = (dielectric(1.4, 1), dielectric(1.6, 1));
+        assert!(is(MediumStack::default().entered(&first).entered(&second).current(), &second));
+    }
+
+    #[test]
+    fn lower_priority_surfaces_are_skipped() {
+        let (glass, water, diamond) = (dielectric(1.5, 1), dielectric(1.33, 2), dielectric(2.4, 3));
+        assert!(MediumStack::default().is_interface(&glass, true));
+
+        let media = MediumStack::default().entered(&glass).entered(&water);
+        // the glass walls inside the water
+        assert!(!media.is_interface(&glass, false));
+        assert!(!media.is_interface(&dielectric(1.5, 1), true));
+        assert!(media.is_interface(&diamond, true));
+        assert!(media.is_interface(&dielectric(1.2, 2), true));
+        assert!(media.is_interface(&water, false));
+        // leaving an object the ray was never known to be in
+        assert!(media.is_interface(&diamond, false));
+    }
+
+    #[test]
+    fn outside_index_is_the_medium_left_behind() {
+        let (glass, water) = (dielectric(1.5, 1), dielectric(1.33, 2));
+        assert_eq!(MediumStack::default().outside_index(&glass, None), 1.0);
+
+        let media = MediumStack::default().entered(&glass);
+        assert_eq!(media.outside_index(&glass, None), 1.0);
+        let media = media.entered(&water);
+        assert_eq!(media.outside_index(&water, None), 1.5);
+        assert_eq!(media.outside_index(&glass, None), 1.33);
+
+        let media = media.left(&water);
+        assert!(is(media.current(), &glass));
+        assert_eq!(media.left(&glass).index(None), 1.0);
+    }
+
+    #[test]
+    fn leaving_keeps_the_order_of_the_rest() {
+        let (a, b, c) = (dielectric(1.1, 0), dielectric(1.2, 0), dielectric(1.3, 0));
+        let media = MediumStack::default().entered(&a).entered(&b).entered(&c);
+
+        // b isn't on top
+        let media = media.left(&b);
+        assert!(is(media.current(), &c));
+        assert!(is(media.left(&c).current(), &a));
+        assert!(media.left(&c).left(&a).current().is_none());
+
+        // leaving an object that isn't there changes nothing
+        let outside = dielectric(1.4, 5);
+        assert!(is(media.left(&outside).current(), &c));
+    }
+
+    #[test]
+    fn nesting_too_deep_is_ignored() {
+        let objects: Vec<Object> = (0..=MAX_NESTING).map(|i| dielectric(1.0 + i as f32 / 10.0, 0)).collect();
+        let media = objects.iter().fold(MediumStack::default(), |media, object| media.entered(object));
+        assert!(is(media.current(), &objects[MAX_NESTING - 1]));
+        assert!(is(media.left(&objects[MAX_NESTING - 1]).current(), &objects[MAX_NESTING - 2]));
+    }
+}
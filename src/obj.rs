@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::ImageError;
+use vector3::Vector3;
+
+use crate::color::Color;
+use crate::object::{Bump, Coloration, Face, Material, Mesh, Object, Principled, Surface};
+use crate::point::Point;
+use crate::rendering::TextureCoords;
+use crate::spectrum::Ior;
+use crate::texture::{Texture, TextureCache, WrapMode};
+use crate::ALBEDO;
+
+/// Errors of loading Wavefront OBJ/MTL files
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, error: io::Error },
+    Image { path: PathBuf, error: ImageError },
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Image { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse { path, line, message } => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Loads an OBJ file (and the MTL libraries it refers to) as a list of meshes.
+/// Every group / material combination becomes a separate `Object::Mesh`.
+/// Diffuse maps are loaded through `textures`.
+pub fn load_obj(path: &Path, textures: &mut TextureCache) -> Result<Vec<Object>, ObjError> {
+    parse_obj(&read(path)?, path, textures)
+}
+
+/// Parses the OBJ `source` of the file at `path`, MTL libraries are read relative to it
+fn parse_obj(source: &str, path: &Path, textures: &mut TextureCache) -> Result<Vec<Object>, ObjError> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut vertices: Vec<Point> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut texture_coords: Vec<TextureCoords> = Vec::new();
+    let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut groups = vec![Group::default()];
+
+    for (number, line) in source.lines().enumerate() {
+        let context = LineContext { path, line: number + 1 };
+        let mut tokens = strip_comment(line).split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = context.floats::<3>(&args, 3)?;
+                vertices.push(Point { x, y, z });
+            }
+            "vn" => {
+                let [x, y, z] = context.floats::<3>(&args, 3)?;
+                normals.push(Vector3 { x, y, z }.normalize());
+            }
+            "vt" => {
+                // v is optional, w is ignored
+                let [u, v] = context.floats::<2>(&args, 1)?;
+                // OBJ puts the origin at the bottom-left corner, images at the top-left one
+                texture_coords.push(TextureCoords { x: u as f32, y: 1.0 - v as f32 });
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(context.error(format!("face needs at least 3 vertices, got {}", args.len())));
+                }
+                let corners = args
+                    .iter()
+                    .map(|arg| context.corner(arg, vertices.len(), texture_coords.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let group = groups.last_mut().unwrap();
+                // fan triangulation of convex polygons
+                for i in 1..corners.len() - 1 {
+                    group.faces.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let material = groups.last().unwrap().material.clone();
+                start_group(&mut groups, material);
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                if !materials.contains_key(&name) {
+                    return Err(context.error(format!("unknown material `{}`", name)));
+                }
+                start_group(&mut groups, Some(name));
+            }
+            "mtllib" => {
+                for library in &args {
+                    materials.extend(load_mtl(&directory.join(library))?);
+                }
+            }
+            // smoothing groups, lines, points and other statements don't affect rendering
+            _ => {}
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|group| !group.faces.is_empty())
+        .map(|group| {
+            let material = match &group.material {
+                Some(name) => materials[name].to_material(textures)?,
+                None => Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive),
+            };
+            Ok(Object::Mesh(group.into_mesh(&vertices, &normals, &texture_coords, material)))
+        })
+        .collect()
+}
+
+/// Faces sharing the same group and material
+#[derive(Default)]
+struct Group {
+    material: Option<String>,
+    faces: Vec<[Corner; 3]>,
+}
+
+impl Group {
+    /// Builds a mesh with compact buffers holding only the data this group refers to
+    fn into_mesh(
+        self,
+        vertices: &[Point],
+        normals: &[Vector3],
+        texture_coords: &[TextureCoords],
+        material: Material,
+    ) -> Mesh {
+        let mut mesh_vertices = Vec::new();
+        let mut mesh_normals = Vec::new();
+        let mut mesh_texture_coords = Vec::new();
+        let mut faces = Vec::with_capacity(self.faces.len());
+        let mut vertex_map = HashMap::new();
+        let mut normal_map = HashMap::new();
+        let mut texture_map = HashMap::new();
+
+        for corners in &self.faces {
+            let vertex_indices =
+                corners.map(|c| remap(c.vertex, &mut vertex_map, &mut mesh_vertices, vertices));
+            let normal_indices = if corners.iter().all(|c| c.normal.is_some()) {
+                Some(corners.map(|c| remap(c.normal.unwrap(), &mut normal_map, &mut mesh_normals, normals)))
+            } else {
+                None
+            };
+            let texture_indices = if corners.iter().all(|c| c.texture.is_some()) {
+                Some(corners.map(|c| {
+                    remap(c.texture.unwrap(), &mut texture_map, &mut mesh_texture_coords, texture_coords)
+                }))
+            } else {
+                None
+            };
+            faces.push(Face {
+                vertices: vertex_indices,
+                normals: normal_indices,
+                texture_coords: texture_indices,
+            });
+        }
+
+        Mesh::new(mesh_vertices, mesh_normals, mesh_texture_coords, faces, material)
+    }
+}
+
+fn start_group(groups: &mut Vec<Group>, material: Option<String>) {
+    let current = groups.last_mut().unwrap();
+    if current.faces.is_empty() {
+        current.material = material;
+    } else {
+        groups.push(Group { material, faces: Vec::new() });
+    }
+}
+
+fn remap<T: Copy>(index: usize, map: &mut HashMap<usize, usize>, target: &mut Vec<T>, source: &[T]) -> usize {
+    *map.entry(index).or_insert_with(|| {
+        target.push(source[index]);
+        target.len() - 1
+    })
+}
+
+/// Zero-based indices of a single face corner
+#[derive(Clone, Copy)]
+struct Corner {
+    vertex: usize,
+    texture: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// MTL material description
+struct MtlMaterial {
+    diffuse: Color,
+    diffuse_map: Option<PathBuf>,
+    /// `-clamp on` option of `map_Kd`
+    diffuse_map_clamp: bool,
+    /// Height map of `bump` / `map_Bump`, scaled by its `-bm` option
+    bump_map: Option<PathBuf>,
+    bump_strength: f32,
+    /// Tangent space normal map of `norm`
+    normal_map: Option<PathBuf>,
+    /// Phong exponent of `Ns`
+    specular_exponent: Option<f32>,
+    index: f32,
+    dissolve: f32,
+    /// Whether any parameter of the PBR extension (`Pm`, `Pr`, `Ps`, `Pc`, `Pcr`) was given
+    pbr: bool,
+    metallic: f32,
+    /// Taken from `Ns` when missing
+    roughness: Option<f32>,
+    sheen: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::WHITE,
+            diffuse_map: None,
+            diffuse_map_clamp: false,
+            bump_map: None,
+            bump_strength: 1.0,
+            normal_map: None,
+            specular_exponent: None,
+            index: 1.0,
+            dissolve: 1.0,
+            pbr: false,
+            metallic: 0.0,
+            roughness: None,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Maps MTL parameters onto the renderer's material model:
+    /// PBR parameters give a principled surface with `1 - d` as its transmission,
+    /// otherwise `d < 1` gives a refractive surface with `Ni` as its index and anything else is diffuse.
+    /// Without `Pr` the principled roughness comes from the Phong exponent `Ns`.
+    /// A `norm` normal map wins over a `bump` height map.
+    fn to_material(&self, textures: &mut TextureCache) -> Result<Material, ObjError> {
+        let constant = |value: f32| Coloration::Color(Color { red: value, green: value, blue: value });
+        let surface = if self.pbr {
+            // Beckmann alpha matching the Phong lobe, alpha is the square of the perceptual roughness
+            let phong = self.specular_exponent.map(|exponent| (2.0 / (exponent.max(0.0) + 2.0)).sqrt().sqrt());
+            Surface::Principled(Box::new(Principled {
+                metallic: constant(self.metallic),
+                roughness: constant(self.roughness.or(phong).unwrap_or(0.5)),
+                specular: constant(0.5),
+                clearcoat: constant(self.clearcoat),
+                clearcoat_roughness: constant(self.clearcoat_roughness),
+                sheen: constant(self.sheen),
+                transmission: constant(1.0 - self.dissolve),
+                index: self.index,
+            }))
+        } else if self.dissolve < 1.0 {
+            Surface::Refractive {
+                transparency: 1.0 - self.dissolve,
+                index: Ior::Constant(self.index),
+                absorption: Color::BLACK,
+            }
+        } else {
+            Surface::Diffusive
+        };
+
+        let mut texture = |path: &PathBuf, srgb: bool| {
+            let image = textures
+                .load(path, srgb)
+                .map_err(|error| ObjError::Image { path: path.clone(), error })?;
+            Ok(Texture::new(image))
+        };
+        let color = match &self.diffuse_map {
+            Some(path) => {
+                let mut texture = texture(path, true)?;
+                if self.diffuse_map_clamp {
+                    texture.wrap = WrapMode::Clamp;
+                }
+                Coloration::Texture { texture, scaling: 1.0, offset: 0.0 }
+            }
+            None => Coloration::Color(self.diffuse),
+        };
+        let mut map = |path: &PathBuf| -> Result<Coloration, ObjError> {
+            Ok(Coloration::Texture { texture: texture(path, false)?, scaling: 1.0, offset: 0.0 })
+        };
+        let bump = match (&self.normal_map, &self.bump_map) {
+            (Some(path), _) => Some(Bump::Normal(map(path)?)),
+            (None, Some(path)) => Some(Bump::Height { height: map(path)?, strength: self.bump_strength }),
+            (None, None) => None,
+        };
+
+        Ok(Material { color, albedo: ALBEDO, surface, bump, priority: 0 })
+    }
+}
+
+/// Loads named materials from an MTL file
+fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    parse_mtl(&read(path)?, path)
+}
+
+/// Parses the MTL `source` of the file at `path`, texture paths are relative to it
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let context = LineContext { path, line: number + 1 };
+        let mut tokens = strip_comment(line).split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let Some((_, material)) = current.as_mut() else {
+            return Err(context.error(format!("`{}` before any `newmtl`", keyword)));
+        };
+        match keyword {
+            "Kd" => {
+                let [red, green, blue] = context.floats::<3>(&args, 3)?;
+                material.diffuse = Color { red: red as f32, green: green as f32, blue: blue as f32 };
+            }
+            "map_Kd" => {
+                // options like `-s 1 1 1` come before the file name
+                let file = args.last().ok_or_else(|| context.error("missing texture path".to_string()))?;
+                material.diffuse_map = Some(directory.join(file));
+                material.diffuse_map_clamp = args.windows(2).any(|option| option == ["-clamp", "on"]);
+            }
+            "bump" | "map_Bump" => {
+                let file = args.last().ok_or_else(|| context.error("missing texture path".to_string()))?;
+                material.bump_map = Some(directory.join(file));
+                if let Some(option) = args.windows(2).find(|option| option[0] == "-bm") {
+                    material.bump_strength = context.floats::<1>(&option[1..], 1)?[0] as f32;
+                }
+            }
+            "norm" => {
+                let file = args.last().ok_or_else(|| context.error("missing texture path".to_string()))?;
+                material.normal_map = Some(directory.join(file));
+            }
+            "Ns" => material.specular_exponent = Some(context.floats::<1>(&args, 1)?[0] as f32),
+            "Ni" => material.index = context.floats::<1>(&args, 1)?[0] as f32,
+            "d" => material.dissolve = context.floats::<1>(&args, 1)?[0] as f32,
+            "Tr" => material.dissolve = 1.0 - context.floats::<1>(&args, 1)?[0] as f32,
+            "Pm" | "Pr" | "Ps" | "Pc" | "Pcr" => {
+                let value = context.floats::<1>(&args, 1)?[0].clamp(0.0, 1.0) as f32;
+                material.pbr = true;
+                match keyword {
+                    "Pm" => material.metallic = value,
+                    "Pr" => material.roughness = Some(value),
+                    "Ps" => material.sheen = value,
+                    "Pc" => material.clearcoat = value,
+                    _ => material.clearcoat_roughness = value,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io { path: path.to_path_buf(), error })
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or("")
+}
+
+/// Position in the file being parsed, used for error reporting
+struct LineContext<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl LineContext<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { path: self.path.to_path_buf(), line: self.line, message }
+    }
+
+    /// Parses up to `N` numbers, at least `required` of them have to be present
+    fn floats<const N: usize>(&self, args: &[&str], required: usize) -> Result<[f64; N], ObjError> {
+        if args.len() < required {
+            return Err(self.error(format!("expected {} numbers, got {}", required, args.len())));
+        }
+        let mut values = [0.0; N];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = arg
+                .parse()
+                .map_err(|_| self.error(format!("invalid number `{}`", arg)))?;
+        }
+        Ok(values)
+    }
+
+    /// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner
+    fn corner(&self, arg: &str, vertices: usize, textures: usize, normals: usize) -> Result<Corner, ObjError> {
+        let mut parts = arg.split('/');
+        let vertex = self.index(parts.next().unwrap_or(""), vertices)?;
+        let texture = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(self.index(part, textures)?),
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(self.index(part, normals)?),
+        };
+        Ok(Corner { vertex, texture, normal })
+    }
+
+    /// Resolves one-based (or negative, relative to the end) OBJ index into a zero-based one
+    fn index(&self, arg: &str, count: usize) -> Result<usize, ObjError> {
+        let index: i64 = arg
+            .parse()
+            .map_err(|_| self.error(format!("invalid index `{}`", arg)))?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("index {} is out of range (have {})", index, count)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Mesh>, ObjError> {
+        let objects = parse_obj(source, Path::new("test.obj"), &mut TextureCache::new())?;
+        Ok(objects
+            .into_iter()
+            .map(|object| match object {
+                Object::Mesh(mesh) => mesh,
+                _ => panic!("expected a mesh"),
+            })
+            .collect())
+    }
+
+    fn face_vertices(mesh: &Mesh) -> Vec<[usize; 3]> {
+        mesh.faces.iter().map(|face| face.vertices).collect()
+    }
+
+    fn parse_error_line(result: Result<Vec<Mesh>, ObjError>) -> usize {
+        match result {
+            Err(ObjError::Parse { line, .. }) => line,
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("malformed input was accepted"),
+        }
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let meshes = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 0 0\nv 6 0 0\nv 5 1 0\nf -3 -2 -1\n").unwrap();
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(face_vertices(mesh), vec![[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(mesh.vertices[3].x, 5.0);
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let meshes = parse(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap();
+        assert_eq!(face_vertices(&meshes[0]), vec![[0, 1, 2], [0, 2, 3]]);
+
+        let meshes = parse(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE)).unwrap();
+        assert_eq!(face_vertices(&meshes[0]), vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn face_corner_forms() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 0.25\nvn 0 0 2\n";
+        let meshes = parse(&format!("{}f 1//1 2//1 3//1\n", source)).unwrap();
+        let face = &meshes[0].faces[0];
+        assert_eq!(face.normals, Some([0, 0, 0]));
+        assert_eq!(face.texture_coords, None);
+        assert_eq!(meshes[0].normals[0].z, 1.0);
+
+        let meshes = parse(&format!("{}f 1/1 2/2 3/3\n", source)).unwrap();
+        let face = &meshes[0].faces[0];
+        assert_eq!(face.normals, None);
+        assert_eq!(face.texture_coords, Some([0, 1, 2]));
+        // flipped to the image convention of the origin at the top
+        assert_eq!(meshes[0].texture_coords[2].y, 0.75);
+
+        let meshes = parse(&format!("{}f 1/1/1 2/2/1 3/3/1\n", source)).unwrap();
+        let face = &meshes[0].faces[0];
+        assert_eq!(face.normals, Some([0, 0, 0]));
+        assert_eq!(face.texture_coords, Some([0, 1, 2]));
+
+        // corners without normals leave the whole face without them
+        let meshes = parse(&format!("{}f 1//1 2 3//1\n", source)).unwrap();
+        assert_eq!(meshes[0].faces[0].normals, None);
+    }
+
+    #[test]
+    fn materials_and_groups_split_meshes() {
+        let directory = std::env::temp_dir();
+        let library = format!("raytracer-{}-groups.mtl", std::process::id());
+        fs::write(directory.join(&library), "newmtl red\nKd 1 0 0\n\nnewmtl blue\nKd 0 0 1\n").unwrap();
+        let source = format!(
+            "mtllib {}\n{}usemtl red\nf 1 2 3\nf 1 3 4\nusemtl blue\nf 1 2 3\ng other\nf 1 3 4\n",
+            library, SQUARE
+        );
+        let objects = parse_obj(&source, &directory.join("groups.obj"), &mut TextureCache::new());
+        fs::remove_file(directory.join(&library)).unwrap();
+
+        let colors: Vec<(usize, f32, f32)> = objects
+            .unwrap()
+            .iter()
+            .map(|object| match object {
+                Object::Mesh(Mesh { faces, material: Material { color: Coloration::Color(c), .. }, .. }) => {
+                    (faces.len(), c.red, c.blue)
+                }
+                _ => panic!("expected a plain colored mesh"),
+            })
+            .collect();
+        // a group keeps the current material
+        assert_eq!(colors, vec![(2, 1.0, 0.0), (1, 0.0, 1.0), (1, 0.0, 1.0)]);
+
+        let line = parse_error_line(parse(&format!("{}usemtl missing\nf 1 2 3\n", SQUARE)));
+        assert_eq!(line, 5);
+    }
+
+    #[test]
+    fn mtl_parameters_map_to_materials() {
+        let source = "newmtl plain\nKd 0.2 0.4 0.6\nNs 250\n\n\
+                      newmtl glass\nKd 1 1 1\nd 0.25\nNi 1.5\n\n\
+                      newmtl metal\nPm 1\nNs 250\n";
+        let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+        let mut textures = TextureCache::new();
+        let mut material = |name: &str| materials[name].to_material(&mut textures).unwrap();
+
+        let plain = material("plain");
+        let Coloration::Color(color) = plain.color else { panic!("expected a color") };
+        assert_eq!([color.red, color.green, color.blue], [0.2, 0.4, 0.6]);
+        // a Phong exponent is no mirror
+        assert!(matches!(plain.surface, Surface::Diffusive));
+
+        match material("glass").surface {
+            Surface::Refractive { transparency, index: Ior::Constant(index), .. } => {
+                assert_eq!(transparency, 0.75);
+                assert_eq!(index, 1.5);
+            }
+            _ => panic!("expected a refractive surface"),
+        }
+
+        match material("metal").surface {
+            Surface::Principled(principled) => {
+                let Coloration::Color(roughness) = principled.roughness else { panic!("expected a constant") };
+                let alpha = roughness.red * roughness.red;
+                assert!((alpha - (2.0f32 / 252.0).sqrt()).abs() < 1e-6);
+            }
+            _ => panic!("expected a principled surface"),
+        }
+    }
+
+    #[test]
+    fn errors_point_to_the_line() {
+        assert_eq!(parse_error_line(parse("v 0 0 0\nv 1 x 0\n")), 2);
+        assert_eq!(parse_error_line(parse("v 0 0 0\n\nvn 1 0\n")), 3);
+        assert_eq!(parse_error_line(parse(&format!("{}f 1 2\n", SQUARE))), 5);
+        assert_eq!(parse_error_line(parse(&format!("{}# comment\nf 1 2 5\n", SQUARE))), 6);
+        assert_eq!(parse_error_line(parse(&format!("{}f 0 1 2\n", SQUARE))), 5);
+        assert_eq!(parse_error_line(parse(&format!("{}f -5 1 2\n", SQUARE))), 5);
+        assert_eq!(parse_error_line(parse(&format!("{}f 1/2 2/1 3/1\n", SQUARE))), 5);
+        assert_eq!(parse_error_line(parse(&format!("{}f 1//1 2 3\n", SQUARE))), 5);
+
+        match parse_mtl("Kd 1 1 1\n", Path::new("test.mtl")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected a parse error"),
+        }
+        match parse_mtl("newmtl a\nd half\n", Path::new("test.mtl")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
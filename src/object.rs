@@ -1,42 +1,114 @@
-use image::{DynamicImage, GenericImageView, ImageReader};
 use vector3::Vector3;
-use crate::{color::Color, point::Point, rendering::{Intersectable, TextureCoords}, ALBEDO};
+use crate::{bsdf::fresnel_dielectric, bvh::{Aabb, Bvh}, color::Color, point::Point, procedural::{Procedural, TextureSpace}, rendering::{differentiate, Footprint, Intersectable, Ray, TextureCoords}, sampling::orthonormal_basis, spectrum::{self, Ior, Wavelengths}, texture::{Texture, TextureDerivatives}};
 
 /// Object definition
 pub enum Object {
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Mesh(Mesh),
 }
 
 impl Object {
-    pub fn color(&self, hit_point: &Point) -> Color {
+    /// Bounding box of the object, `None` for unbounded objects like planes
+    pub fn bounds(&self) -> Option<Aabb> {
         match self {
-            Object::Plane(plane) => plane.material.color.color(&self.texture_coords(hit_point)),
-            Object::Sphere(sphere) => sphere.material.color.color(&self.texture_coords(hit_point)),
+            Object::Sphere(sphere) => {
+                let radius = Point { x: sphere.radius, y: sphere.radius, z: sphere.radius };
+                Some(Aabb { min: sphere.center - radius, max: sphere.center + radius })
+            }
+            Object::Plane(_) => None,
+            Object::Triangle(triangle) => Some(Aabb::from_points(&triangle.vertices)),
+            Object::Mesh(mesh) => Some(Aabb::from_points(&mesh.vertices)),
         }
     }
-    pub fn albedo(&self) -> f32 {
+    /// Origin of the object space of procedural textures: the center of spheres and the origin of planes.
+    /// Triangles and meshes have no transform, their object space is world space.
+    pub fn origin(&self) -> Point {
         match self {
-            Object::Plane(plane) => plane.material.albedo,
-            Object::Sphere(sphere) => sphere.material.albedo,
+            Object::Sphere(sphere) => sphere.center,
+            Object::Plane(plane) => plane.origin,
+            Object::Triangle(_) | Object::Mesh(_) => Point::zero(),
+        }
+    }
+    /// Everything colorations are evaluated from at the hit point
+    pub fn surface_coords(&self, hit_point: &Point, face: usize, footprint: &Footprint) -> SurfaceCoords {
+        let (texture_coords, derivatives) = self.texture_coords(hit_point, face, footprint);
+        SurfaceCoords {
+            texture_coords,
+            derivatives,
+            world: *hit_point,
+            local: *hit_point - self.origin(),
+            footprint: *footprint,
         }
     }
-    pub fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    pub fn material(&self) -> &Material {
+        match self {
+            Object::Sphere(sphere) => &sphere.material,
+            Object::Plane(plane) => &plane.material,
+            Object::Triangle(triangle) => &triangle.material,
+            Object::Mesh(mesh) => &mesh.material,
+        }
+    }
+    pub fn color(&self, hit_point: &Point, face: usize, footprint: &Footprint) -> Color {
+        self.material().color.color(&self.surface_coords(hit_point, face, footprint))
+    }
+    pub fn albedo(&self) -> f32 {
+        self.material().albedo
+    }
+    pub fn surface_normal(&self, hit_point: &Point, face: usize) -> Vector3 {
         match self {
             Object::Plane(plane) => plane.surface_normal(hit_point),
             Object::Sphere(sphere) => sphere.surface_normal(hit_point),
+            Object::Triangle(triangle) => triangle.surface_normal(hit_point),
+            Object::Mesh(mesh) => mesh.face_surface_normal(hit_point, face),
         }
     }
-    pub fn surface_type(&self) -> Surface {
-        match self {
-            Object::Plane(plane) => plane.material.surface,
-            Object::Sphere(sphere) => sphere.material.surface,
+    /// The surface normal `normal` perturbed by the material's normal or bump map
+    pub fn shading_normal(&self, hit_point: &Point, face: usize, footprint: &Footprint, normal: Vector3) -> Vector3 {
+        match &self.material().bump {
+            Some(bump) => {
+                let (dp_du, dp_dv) = self.tangents(hit_point, face);
+                bump.perturb(normal, dp_du, dp_dv, &self.surface_coords(hit_point, face, footprint))
+            }
+            None => normal,
         }
     }
+    pub fn surface_type(&self) -> &Surface {
+        &self.material().surface
+    }
+    /// Fraction of light a straight shadow ray keeps crossing the surface at the hit point: black for opaque
+    /// surfaces, transparent ones take off their Fresnel reflection and tint rays entering them with their color.
+    /// `outside` is the index of refraction of the medium around the object.
+    pub fn transmittance(&self, hit_point: &Point, face: usize, ray: &Ray, outside: f32) -> Color {
+        let normal = self.surface_normal(hit_point, face).normalize();
+        let cos_i = -ray.direction.dot(&normal);
+        let coords = self.surface_coords(hit_point, face, &Footprint::default());
+        let fraction = match *self.surface_type() {
+            Surface::Refractive { transparency, index, .. } => {
+                transparency as f64 * (1.0 - ray.fresnel(normal, index.index(ray.wavelengths), outside))
+            }
+            Surface::RoughDielectric { index, .. } => {
+                1.0 - fresnel_dielectric(cos_i, (index.index(ray.wavelengths) / outside) as f64)
+            }
+            Surface::Principled(ref principled) => {
+                let parameter = |coloration: &Coloration| coloration.value(&coords).clamp(0.0, 1.0) as f64;
+                let transmission = (1.0 - parameter(&principled.metallic)) * parameter(&principled.transmission);
+                transmission * (1.0 - fresnel_dielectric(cos_i, (principled.index / outside) as f64))
+            }
+            _ => return Color::BLACK,
+        };
+        let tint = if cos_i > 0.0 {
+            spectrum::at(self.material().color.color(&coords), ray.wavelengths)
+        } else {
+            Color::WHITE
+        };
+        tint * fraction as f32
+    }
 }
 
 // Object primitives: vvv
- 
+
 /// Object structs: Sphere
 pub struct Sphere {
     pub center: Point,
@@ -63,70 +135,539 @@ impl Plane {
     }
 }
 
+/// Object structs: Triangle
+/// Vertices go counter-clockwise when looking at the front side.
+/// Per-vertex normals and texture coordinates are optional.
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    pub normals: Option<[Vector3; 3]>,
+    pub texture_coords: Option<[TextureCoords; 3]>,
+    pub material: Material,
+}
+
+impl Triangle {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let [a, b, c] = self.vertices;
+        match self.normals {
+            Some(normals) => interpolate_normal(&normals, barycentric(hit_point, a, b, c)),
+            None => geometric_normal(a, b, c),
+        }
+    }
+}
+
+/// Object structs: Mesh
+/// Indexed triangle mesh: faces refer to the shared vertex, normal and texture coordinate buffers.
+pub struct Mesh {
+    pub vertices: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub texture_coords: Vec<TextureCoords>,
+    pub faces: Vec<Face>,
+    pub material: Material,
+    bvh: Bvh,
+}
+
+/// Single mesh triangle, holding indices into the `Mesh` buffers
+#[derive(Clone, Copy)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub texture_coords: Option<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn new(
+        vertices: Vec<Point>,
+        normals: Vec<Vector3>,
+        texture_coords: Vec<TextureCoords>,
+        faces: Vec<Face>,
+        material: Material,
+    ) -> Mesh {
+        let bounds: Vec<Aabb> = faces
+            .iter()
+            .map(|face| Aabb::from_points(&face.vertices.map(|i| vertices[i])))
+            .collect();
+        Mesh { bvh: Bvh::build(&bounds), vertices, normals, texture_coords, faces, material }
+    }
+
+    fn face_vertices(&self, face: usize) -> (Point, Point, Point) {
+        let [a, b, c] = self.faces[face].vertices;
+        (self.vertices[a], self.vertices[b], self.vertices[c])
+    }
+
+    fn face_surface_normal(&self, hit_point: &Point, face: usize) -> Vector3 {
+        let (a, b, c) = self.face_vertices(face);
+        match self.faces[face].normals {
+            Some([na, nb, nc]) => interpolate_normal(
+                &[self.normals[na], self.normals[nb], self.normals[nc]],
+                barycentric(hit_point, a, b, c),
+            ),
+            None => geometric_normal(a, b, c),
+        }
+    }
+
+    fn face_texture_coords(&self, hit_point: &Point, face: usize) -> TextureCoords {
+        let (a, b, c) = self.face_vertices(face);
+        let weights = barycentric(hit_point, a, b, c);
+        match self.faces[face].texture_coords {
+            Some([ta, tb, tc]) => interpolate_texture_coords(
+                &[self.texture_coords[ta], self.texture_coords[tb], self.texture_coords[tc]],
+                weights,
+            ),
+            None => TextureCoords { x: weights.1 as f32, y: weights.2 as f32 },
+        }
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)> {
+        self.bvh
+            .intersect(ray, |i| {
+                let (a, b, c) = self.face_vertices(i);
+                intersect_triangle(ray, a, b, c).map(|d| (d, ()))
+            })
+            .map(|(distance, face, _)| (distance, face))
+    }
+
+    fn texture_coords(&self, hit_point: &Point, face: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives) {
+        differentiate(|point| self.face_texture_coords(point, face), hit_point, footprint, false)
+    }
+
+    fn tangents(&self, _: &Point, face: usize) -> (Vector3, Vector3) {
+        let (a, b, c) = self.face_vertices(face);
+        let coords = match self.faces[face].texture_coords {
+            Some(indices) => indices.map(|i| self.texture_coords[i]),
+            None => BARYCENTRIC_COORDS,
+        };
+        triangle_tangents([a, b, c], coords)
+    }
+}
+
+// Triangle helpers: vvv
+
+/// Möller–Trumbore ray-triangle intersection, both sides of the triangle are hit
+/// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+pub fn intersect_triangle(ray: &Ray, a: Point, b: Point, c: Point) -> Option<f64> {
+    const EPSILON: f64 = 1e-12;
+
+    let edge1 = Vector3::from(b - a);
+    let edge2 = Vector3::from(c - a);
+    let p = ray.direction.cross(&edge2);
+    let det = edge1.dot(&p);
+    // the ray is parallel to the triangle (or the triangle is degenerate)
+    if det.abs() < EPSILON * edge1.magnitude() * edge2.magnitude() {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = Vector3::from(ray.origin - a);
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(&q) * inv_det;
+    if distance > EPSILON {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+fn geometric_normal(a: Point, b: Point, c: Point) -> Vector3 {
+    Vector3::from(b - a).cross(&Vector3::from(c - a)).normalize()
+}
+
+/// Barycentric weights (w_a, w_b, w_c) of the point projected onto the triangle's plane
+pub fn barycentric(p: &Point, a: Point, b: Point, c: Point) -> (f64, f64, f64) {
+    let v0 = Vector3::from(b - a);
+    let v1 = Vector3::from(c - a);
+    let v2 = Vector3::from(*p - a);
+    let d00 = v0.dot(&v0);
+    let d01 = v0.dot(&v1);
+    let d11 = v1.dot(&v1);
+    let d20 = v2.dot(&v0);
+    let d21 = v2.dot(&v1);
+    let denom = d00 * d11 - d01 * d01;
+    let w_b = (d11 * d20 - d01 * d21) / denom;
+    let w_c = (d00 * d21 - d01 * d20) / denom;
+    (1.0 - w_b - w_c, w_b, w_c)
+}
+
+fn interpolate_normal(normals: &[Vector3; 3], (w_a, w_b, w_c): (f64, f64, f64)) -> Vector3 {
+    (normals[0] * w_a + normals[1] * w_b + normals[2] * w_c).normalize()
+}
+
+/// Texture coordinates of the corners of triangles without any, the coordinates are the barycentric weights of `b` and `c`
+pub const BARYCENTRIC_COORDS: [TextureCoords; 3] =
+    [TextureCoords { x: 0.0, y: 0.0 }, TextureCoords { x: 1.0, y: 0.0 }, TextureCoords { x: 0.0, y: 1.0 }];
+
+/// (dp/du, dp/dv) of the triangle with the given corner texture coordinates, zero when they are degenerate
+pub fn triangle_tangents([a, b, c]: [Point; 3], [ta, tb, tc]: [TextureCoords; 3]) -> (Vector3, Vector3) {
+    let (edge1, edge2) = (Vector3::from(b - a), Vector3::from(c - a));
+    let (du1, dv1) = ((tb.x - ta.x) as f64, (tb.y - ta.y) as f64);
+    let (du2, dv2) = ((tc.x - ta.x) as f64, (tc.y - ta.y) as f64);
+    let determinant = du1 * dv2 - du2 * dv1;
+    if determinant.abs() < 1e-12 {
+        return (Vector3::zero(), Vector3::zero());
+    }
+    (
+        (edge1 * dv2 - edge2 * dv1) / determinant,
+        (edge2 * du1 - edge1 * du2) / determinant,
+    )
+}
+
+pub fn interpolate_texture_coords(coords: &[TextureCoords; 3], (w_a, w_b, w_c): (f64, f64, f64)) -> TextureCoords {
+    let (w_a, w_b, w_c) = (w_a as f32, w_b as f32, w_c as f32);
+    TextureCoords {
+        x: coords[0].x * w_a + coords[1].x * w_b + coords[2].x * w_c,
+        y: coords[0].y * w_a + coords[1].y * w_b + coords[2].y * w_c,
+    }
+}
+
 /// Textures and shit
 pub struct Material {
     pub color: Coloration,
     pub albedo: f32,
     pub surface: Surface,
+    pub bump: Option<Bump>,
+    /// Which of overlapping dielectrics fills their shared volume, the highest one does
+    pub priority: u32,
 }
 
 impl Material {
     pub fn from_color(color: Color, albedo: f32, surface: Surface) -> Material {
-        Material { color: Coloration::Color(color), albedo: albedo, surface: surface }
-    }
-
-    pub fn get_texture(path: &str, scaling: f32, offset: f32, surface: Surface) -> Material {
-        let img = ImageReader::open(path).unwrap().decode();
-        Material { color: Coloration::Texture{image: img.unwrap(), scaling, offset}, albedo: ALBEDO, surface: surface }
+        Material { color: Coloration::Color(color), albedo, surface, bump: None, priority: 0 }
     }
-
-    pub const CHECKERBOARD: &str = "textures/checkerboard6.png";
-    pub const FLOOR: &str = "textures/floor1.jpg";
-    pub const WOOD: &str = "textures/wood4.png";
 }
 
 pub enum Coloration {
-    Color(Color), 
-    Texture { image: DynamicImage, scaling: f32, offset: f32 }
+    Color(Color),
+    /// Texture coordinates are multiplied by `scaling`, then shifted by `offset`
+    Texture { texture: Texture, scaling: f32, offset: f32 },
+    Procedural(Box<Procedural>),
+}
+
+/// Where a coloration is evaluated: image textures read the texture coordinates,
+/// procedural ones the position
+#[derive(Clone, Copy)]
+pub struct SurfaceCoords {
+    pub texture_coords: TextureCoords,
+    pub derivatives: TextureDerivatives,
+    pub world: Point,
+    /// Hit point relative to `Object::origin`
+    pub local: Point,
+    pub footprint: Footprint,
 }
 
 impl Coloration {
-    fn color(&self, texture_coords: &TextureCoords) -> Color {
+    pub fn color(&self, surface_coords: &SurfaceCoords) -> Color {
         match self {
             Coloration::Color(c) => *c,
-            Coloration::Texture { image, scaling , offset} => {
-                let tex_x = wrap(texture_coords.x * scaling + offset, image.width());
-                let tex_y = wrap(texture_coords.y * scaling + offset, image.height());
-                Color::from_rgba(image.get_pixel(tex_x, tex_y))
-            },
+            Coloration::Texture { texture, scaling, offset } => {
+                let SurfaceCoords { texture_coords, derivatives, .. } = surface_coords;
+                let coords = TextureCoords {
+                    x: texture_coords.x * scaling + offset,
+                    y: texture_coords.y * scaling + offset,
+                };
+                let derivatives = TextureDerivatives {
+                    du_dx: derivatives.du_dx * scaling,
+                    dv_dx: derivatives.dv_dx * scaling,
+                    du_dy: derivatives.du_dy * scaling,
+                    dv_dy: derivatives.dv_dy * scaling,
+                };
+                texture.lookup(coords, &derivatives)
+            }
+            Coloration::Procedural(procedural) => {
+                let position = match procedural.space {
+                    TextureSpace::Object => surface_coords.local,
+                    TextureSpace::World => surface_coords.world,
+                };
+                procedural.color(position, surface_coords.footprint.dp_dx, surface_coords.footprint.dp_dy)
+            }
         }
     }
+
+    /// Scalar parameter: the red channel of the color (textures holding data are loaded without sRGB decoding)
+    pub fn value(&self, surface_coords: &SurfaceCoords) -> f32 {
+        self.color(surface_coords).red
+    }
 }
 
-fn wrap(val: f32, bound: u32) -> u32 {
-    let signed_bound = bound as i32;
-    let float_coord = val * bound as f32;
-    let wrapped_coord = (float_coord as i32) % signed_bound;
-    if wrapped_coord < 0 {
-        (wrapped_coord + signed_bound) as u32
-    } else {
-        wrapped_coord as u32
+/// Fine surface detail changing the shading normal but not the geometry
+pub enum Bump {
+    /// Tangent space normal map: red, green and blue in [0, 1] hold the components in [-1, 1]
+    /// along the texture's u axis, up the image and along the surface normal
+    Normal(Coloration),
+    /// Height map: the surface is lifted along its normal by the value times `strength` scene units
+    Height { height: Coloration, strength: f32 },
+}
+
+impl Bump {
+    /// Perturbs the unit `normal`, `dp_du` and `dp_dv` are the derivatives of the position by the texture coordinates
+    pub fn perturb(&self, normal: Vector3, dp_du: Vector3, dp_dv: Vector3, coords: &SurfaceCoords) -> Vector3 {
+        let perturbed = match self {
+            Bump::Normal(map) => {
+                let texel = map.color(coords);
+                let tangent = dp_du - normal * normal.dot(&dp_du);
+                let (tangent, mut bitangent) = if tangent.magnitude() > 1e-12 {
+                    let tangent = tangent.normalize();
+                    (tangent, normal.cross(&tangent))
+                } else {
+                    orthonormal_basis(normal)
+                };
+                // v grows down the image
+                if bitangent.dot(&dp_dv) > 0.0 {
+                    bitangent = Vector3::zero() - bitangent;
+                }
+                tangent * (2.0 * texel.red as f64 - 1.0)
+                    + bitangent * (2.0 * texel.green as f64 - 1.0)
+                    + normal * (2.0 * texel.blue as f64 - 1.0)
+            }
+            Bump::Height { height, strength } => {
+                // forward differences over about half the pixel footprint
+                let step = |a: f32, b: f32| match 0.5 * (a.abs() + b.abs()) {
+                    step if step > 0.0 => step,
+                    _ => 0.0005,
+                };
+                let du = step(coords.derivatives.du_dx, coords.derivatives.du_dy);
+                let dv = step(coords.derivatives.dv_dx, coords.derivatives.dv_dy);
+                let height_at = |du: f32, dv: f32| {
+                    let shift = dp_du * du as f64 + dp_dv * dv as f64;
+                    let mut shifted = *coords;
+                    shifted.texture_coords.x += du;
+                    shifted.texture_coords.y += dv;
+                    shifted.world = shifted.world + shift.into();
+                    shifted.local = shifted.local + shift.into();
+                    height.value(&shifted)
+                };
+                let center = height.value(coords);
+                let dh_du = ((height_at(du, 0.0) - center) / du * strength) as f64;
+                let dh_dv = ((height_at(0.0, dv) - center) / dv * strength) as f64;
+                let cross = dp_du.cross(&dp_dv);
+                let bumped = (dp_du + normal * dh_du).cross(&(dp_dv + normal * dh_dv));
+                if cross.dot(&normal) < 0.0 {
+                    Vector3::zero() - bumped
+                } else {
+                    bumped
+                }
+            }
+        };
+        // degenerate tangents, or a map pointing below the surface
+        if perturbed.magnitude() < 1e-12 || perturbed.dot(&normal) <= 0.0 {
+            return normal;
+        }
+        perturbed.normalize()
     }
 }
 
 /// Surfaces
-#[derive(Clone, Copy)]
 pub enum Surface {
     Diffusive,
     Reflective { reflectivity: f32 },
-    Refractive { transparency: f32, index: f32 },
+    /// `absorption` is the fraction of light the medium inside absorbs per unit of length, per channel
+    Refractive { transparency: f32, index: Ior, absorption: Color },
+    /// Rough metal reflecting the material color, GGX microfacets
+    Conductor { roughness: f32 },
+    /// Rough glass, GGX microfacets
+    RoughDielectric { roughness: f32, index: Ior, absorption: Color },
+    Principled(Box<Principled>),
 }
 
 impl Surface {
     pub fn in_transparent(&self) -> bool {
+        matches!(self, Self::Refractive { .. } | Self::RoughDielectric { .. })
+    }
+
+    /// Index of refraction of the medium behind the surface, `None` for surfaces light doesn't cross
+    pub fn index(&self, wavelengths: Option<Wavelengths>) -> Option<f32> {
+        match self {
+            Surface::Refractive { index, .. } | Surface::RoughDielectric { index, .. } => {
+                Some(index.index(wavelengths))
+            }
+            // without transmission it is opaque
+            Surface::Principled(principled) => match principled.transmission {
+                Coloration::Color(transmission) if transmission.luminance() <= 0.0 => None,
+                _ => Some(principled.index),
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the index of refraction depends on the wavelength
+    pub fn is_dispersive(&self) -> bool {
         match self {
-            Self::Refractive { transparency: _, index: _ } => true,
+            Surface::Refractive { index, .. } | Surface::RoughDielectric { index, .. } => index.is_dispersive(),
             _ => false,
         }
     }
-}
\ No newline at end of file
+
+    /// Absorption coefficient of the medium behind the surface, black when it absorbs nothing
+    pub fn absorption(&self) -> Color {
+        match *self {
+            Surface::Refractive { absorption, .. } | Surface::RoughDielectric { absorption, .. } => absorption,
+            _ => Color::BLACK,
+        }
+    }
+}
+
+/// Uber material in the base color / metallic / roughness style of glTF and Blender.
+/// The base color is the material's color (`albedo` is not used), the other parameters
+/// lie in [0, 1] and can be textured.
+pub struct Principled {
+    pub metallic: Coloration,
+    pub roughness: Coloration,
+    /// Reflectance of the dielectric base at normal incidence, 0.5 gives 4%
+    pub specular: Coloration,
+    /// Weight of a white glossy layer on top of the base
+    pub clearcoat: Coloration,
+    pub clearcoat_roughness: Coloration,
+    /// Weight of a soft white rim at grazing angles, for cloth
+    pub sheen: Coloration,
+    /// Weight of the refracting part of the dielectric base
+    pub transmission: Coloration,
+    /// Index of refraction for transmission
+    pub index: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ALBEDO;
+
+    fn point(x: f64, y: f64, z: f64) -> Point {
+        Point { x, y, z }
+    }
+
+    fn vector(x: f64, y: f64, z: f64) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
+    /// Right triangle in the z = 0 plane with its corners at the origin, x = 1 and y = 1
+    fn corners() -> [Point; 3] {
+        [point(0.0, 0.0, 0.0), point(1.0, 0.0, 0.0), point(0.0, 1.0, 0.0)]
+    }
+
+    /// Straight down onto the z = 0 plane at (x, y)
+    fn ray_down(x: f64, y: f64) -> Ray {
+        Ray::new(point(x, y, 2.0), vector(0.0, 0.0, -1.0))
+    }
+
+    fn triangle(normals: Option<[Vector3; 3]>, texture_coords: Option<[TextureCoords; 3]>) -> Triangle {
+        Triangle {
+            vertices: corners(),
+            normals,
+            texture_coords,
+            material: Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn triangle_hit_inside() {
+        let [a, b, c] = corners();
+        assert_close(intersect_triangle(&ray_down(0.25, 0.25), a, b, c).unwrap(), 2.0);
+        // both sides are hit
+        let up = Ray::new(point(0.25, 0.25, -3.0), vector(0.0, 0.0, 1.0));
+        assert_close(intersect_triangle(&up, a, b, c).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn triangle_miss() {
+        let [a, b, c] = corners();
+        // beyond the hypotenuse, and on the far side of each leg
+        assert!(intersect_triangle(&ray_down(0.6, 0.6), a, b, c).is_none());
+        assert!(intersect_triangle(&ray_down(-0.1, 0.5), a, b, c).is_none());
+        assert!(intersect_triangle(&ray_down(0.5, -0.1), a, b, c).is_none());
+        // pointing away from the triangle
+        let away = Ray::new(point(0.25, 0.25, 2.0), vector(0.0, 0.0, 1.0));
+        assert!(intersect_triangle(&away, a, b, c).is_none());
+    }
+
+    #[test]
+    fn triangle_edges_and_corners() {
+        let [a, b, c] = corners();
+        // points on the edges and corners belong to the triangle
+        for (x, y) in [(0.5, 0.5), (0.5, 0.0), (0.0, 0.5), (0.0, 0.0), (1.0, 0.0), (0.0, 1.0)] {
+            assert_close(intersect_triangle(&ray_down(x, y), a, b, c).unwrap(), 2.0);
+        }
+        // rays in the triangle's plane never hit it
+        let grazing = Ray::new(point(-1.0, 0.25, 0.0), vector(1.0, 0.0, 0.0));
+        assert!(intersect_triangle(&grazing, a, b, c).is_none());
+        // neither do degenerate triangles
+        assert!(intersect_triangle(&ray_down(0.25, 0.0), a, b, point(2.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn barycentric_weights() {
+        let [a, b, c] = corners();
+        let (w_a, w_b, w_c) = barycentric(&point(0.2, 0.3, 0.0), a, b, c);
+        assert_close(w_a, 0.5);
+        assert_close(w_b, 0.2);
+        assert_close(w_c, 0.3);
+    }
+
+    #[test]
+    fn interpolated_normals() {
+        let normals = [vector(0.0, 0.0, 1.0), vector(1.0, 0.0, 0.0), vector(0.0, 1.0, 0.0)];
+        let triangle = triangle(Some(normals), None);
+        for (corner, normal) in corners().iter().zip(normals) {
+            let interpolated = triangle.surface_normal(corner);
+            assert_close(interpolated.dot(&normal), 1.0);
+        }
+        let center = triangle.surface_normal(&point(1.0 / 3.0, 1.0 / 3.0, 0.0));
+        let expected = vector(1.0, 1.0, 1.0).normalize();
+        assert_close(center.dot(&expected), 1.0);
+        // without normals the geometric one is used, facing the side the corners go counter-clockwise around
+        assert_close(self::triangle(None, None).surface_normal(&point(0.2, 0.2, 0.0)).z, 1.0);
+    }
+
+    #[test]
+    fn interpolated_texture_coords() {
+        let coords = [TextureCoords { x: 0.0, y: 0.0 }, TextureCoords { x: 1.0, y: 0.0 }, TextureCoords { x: 1.0, y: 1.0 }];
+        let triangle = triangle(None, Some(coords));
+        let (uv, _) = triangle.texture_coords(&point(0.5, 0.25, 0.0), 0, &Footprint::default());
+        assert_close(uv.x as f64, 0.75);
+        assert_close(uv.y as f64, 0.25);
+        // without coordinates they are the barycentric weights of the second and third corner
+        let (uv, _) = self::triangle(None, None).texture_coords(&point(0.2, 0.3, 0.0), 0, &Footprint::default());
+        assert_close(uv.x as f64, 0.2);
+        assert_close(uv.y as f64, 0.3);
+    }
+
+    #[test]
+    fn mesh_hits_report_their_face() {
+        // unit square split along its diagonal, with texture coordinates for the second face only
+        let mesh = Mesh::new(
+            vec![point(0.0, 0.0, 0.0), point(1.0, 0.0, 0.0), point(1.0, 1.0, 0.0), point(0.0, 1.0, 0.0)],
+            vec![],
+            vec![TextureCoords { x: 0.0, y: 0.0 }, TextureCoords { x: 1.0, y: 1.0 }, TextureCoords { x: 0.0, y: 1.0 }],
+            vec![
+                Face { vertices: [0, 1, 2], normals: None, texture_coords: None },
+                Face { vertices: [0, 2, 3], normals: None, texture_coords: Some([0, 1, 2]) },
+            ],
+            Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive),
+        );
+        let (distance, face) = mesh.intersect(&ray_down(0.75, 0.25)).unwrap();
+        assert_close(distance, 2.0);
+        assert_eq!(face, 0);
+        let (distance, face) = mesh.intersect(&ray_down(0.25, 0.75)).unwrap();
+        assert_close(distance, 2.0);
+        assert_eq!(face, 1);
+        assert!(mesh.intersect(&ray_down(1.5, 0.5)).is_none());
+
+        let object = Object::Mesh(mesh);
+        assert_eq!(object.intersect(&ray_down(0.25, 0.75)).map(|(_, face)| face), Some(1));
+        let (uv, _) = object.texture_coords(&point(0.25, 0.75, 0.0), 1, &Footprint::default());
+        assert_close(uv.x as f64, 0.25);
+        assert_close(uv.y as f64, 0.75);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use exr::prelude::f16;
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageError, Rgb};
+
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::tone_mapping::ToneMapping;
+
+/// Errors of writing rendered images
+#[derive(Debug)]
+pub enum OutputError {
+    Io { path: PathBuf, error: io::Error },
+    Image { path: PathBuf, error: ImageError },
+    Exr { path: PathBuf, error: exr::error::Error },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            OutputError::Image { path, error } => write!(f, "{}: {}", path.display(), error),
+            OutputError::Exr { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+/// Sample type of OpenEXR output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+impl FromStr for ExrPrecision {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "half" => Ok(ExrPrecision::Half),
+            "float" => Ok(ExrPrecision::Float),
+            _ => Err(format!("unknown precision `{}`, expected one of `half`, `float`", name)),
+        }
+    }
+}
+
+/// Writes the render in the format given by the file extension.
+/// OpenEXR (`.exr`), Radiance (`.hdr`) and PFM (`.pfm`) files keep the linear radiance as is,
+/// everything else is tone mapped to 8-bit sRGB.
+pub fn save_render(
+    framebuffer: &Framebuffer,
+    path: &Path,
+    tone_mapping: &ToneMapping,
+    exr_precision: ExrPrecision,
+) -> Result<(), OutputError> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    match extension.as_str() {
+        "exr" => save_exr(framebuffer, path, exr_precision),
+        "hdr" => save_radiance_hdr(framebuffer, path),
+        "pfm" => save_pfm(framebuffer, path),
+        _ => framebuffer
+            .to_image(tone_mapping)
+            .save(path)
+            .map_err(|error| OutputError::Image { path: path.to_path_buf(), error }),
+    }
+}
+
+fn save_exr(framebuffer: &Framebuffer, path: &Path, precision: ExrPrecision) -> Result<(), OutputError> {
+    let (width, height) = (framebuffer.width as usize, framebuffer.height as usize);
+    let pixel = |x: usize, y: usize| framebuffer.pixel(x as u32, y as u32);
+    let result = match precision {
+        ExrPrecision::Half => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let Color { red, green, blue } = pixel(x, y);
+            (f16::from_f32(red), f16::from_f32(green), f16::from_f32(blue))
+        }),
+        ExrPrecision::Float => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let Color { red, green, blue } = pixel(x, y);
+            (red, green, blue)
+        }),
+    };
+    result.map_err(|error| OutputError::Exr { path: path.to_path_buf(), error })
+}
+
+fn save_radiance_hdr(framebuffer: &Framebuffer, path: &Path) -> Result<(), OutputError> {
+    let mut pixels = Vec::with_capacity((framebuffer.width * framebuffer.height) as usize);
+    for y in 0..framebuffer.height {
+        for x in 0..framebuffer.width {
+            // RGBE can't store negative values
+            let Color { red, green, blue } = framebuffer.pixel(x, y);
+            pixels.push(Rgb([red.max(0.0), green.max(0.0), blue.max(0.0)]));
+        }
+    }
+    let file = File::create(path).map_err(|error| OutputError::Io { path: path.to_path_buf(), error })?;
+    HdrEncoder::new(BufWriter::new(file))
+        .encode(&pixels, framebuffer.width as usize, framebuffer.height as usize)
+        .map_err(|error| OutputError::Image { path: path.to_path_buf(), error })
+}
+
+/// Portable float map: little-endian RGB floats, rows stored bottom to top
+/// https://www.pauldebevec.com/Research/HDR/PFM/
+fn save_pfm(framebuffer: &Framebuffer, path: &Path) -> Result<(), OutputError> {
+    let write = || -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // a negative scale marks little-endian data
+        write!(file, "PF\n{} {}\n-1.0\n", framebuffer.width, framebuffer.height)?;
+        for y in (0..framebuffer.height).rev() {
+            for x in 0..framebuffer.width {
+                let Color { red, green, blue } = framebuffer.pixel(x, y);
+                for value in [red, green, blue] {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        file.flush()
+    };
+    write().map_err(|error| OutputError::Io { path: path.to_path_buf(), error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn pfm_is_little_endian_from_the_bottom_row() {
+        let (width, height) = (2, 3);
+        let mut framebuffer = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = (y * width + x) as f32;
+                framebuffer.add(x as i64, y as i64, Color { red: value, green: value + 0.25, blue: -value }, 1.0);
+            }
+        }
+        let path = std::env::temp_dir().join(format!("raytracer-{}-rows.pfm", std::process::id()));
+        save_render(&framebuffer, &path, &ToneMapping::default(), ExrPrecision::Float).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n2 3\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let values: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(values.len(), (width * height * 3) as usize);
+        // the top row of the image comes last
+        let expected: Vec<f32> = (0..height)
+            .rev()
+            .flat_map(|y| (0..width).map(move |x| (y * width + x) as f32))
+            .flat_map(|value| [value, value + 0.25, -value])
+            .collect();
+        assert_eq!(values, expected);
+    }
+}
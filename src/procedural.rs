@@ -0,0 +1,288 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use vector3::Vector3;
+
+use crate::color::Color;
+use crate::point::Point;
+
+/// Scalar field a procedural texture maps through its color ramp
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pattern {
+    /// Perlin gradient noise
+    Noise,
+    /// Fractal Brownian motion: octaves of noise, each twice the frequency and half the amplitude
+    Fbm,
+    /// Like fBm with the absolute value of each octave, billowy
+    Turbulence,
+    /// Sine veins along x, bent by turbulence
+    Marble,
+    /// Rings around the y axis, bent by noise
+    Wood,
+    /// Distance to the nearest Worley feature point
+    Worley,
+    /// Random value per Voronoi cell of the Worley feature points
+    Voronoi,
+    /// Alternating unit cubes, box filtered over the pixel footprint
+    Checker,
+}
+
+impl Pattern {
+    pub const NAMES: &str = "`noise`, `fbm`, `turbulence`, `marble`, `wood`, `worley`, `voronoi`, `checker`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Noise => "noise",
+            Pattern::Fbm => "fbm",
+            Pattern::Turbulence => "turbulence",
+            Pattern::Marble => "marble",
+            Pattern::Wood => "wood",
+            Pattern::Worley => "worley",
+            Pattern::Voronoi => "voronoi",
+            Pattern::Checker => "checker",
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "noise" => Ok(Pattern::Noise),
+            "fbm" => Ok(Pattern::Fbm),
+            "turbulence" => Ok(Pattern::Turbulence),
+            "marble" => Ok(Pattern::Marble),
+            "wood" => Ok(Pattern::Wood),
+            "worley" => Ok(Pattern::Worley),
+            "voronoi" => Ok(Pattern::Voronoi),
+            "checker" => Ok(Pattern::Checker),
+            _ => Err(format!("unknown procedural texture `{}`, expected one of {}", name, Pattern::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Coordinate system procedural textures are evaluated in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextureSpace {
+    /// Relative to the object (see `Object::origin`), the texture moves along with it
+    Object,
+    World,
+}
+
+impl TextureSpace {
+    pub const NAMES: &str = "`object`, `world`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureSpace::Object => "object",
+            TextureSpace::World => "world",
+        }
+    }
+}
+
+impl FromStr for TextureSpace {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "object" => Ok(TextureSpace::Object),
+            "world" => Ok(TextureSpace::World),
+            _ => Err(format!("unknown texture space `{}`, expected one of {}", name, TextureSpace::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for TextureSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Piecewise linear map from [0, 1] to colors
+pub struct ColorRamp {
+    /// Positions in ascending order, with their colors
+    stops: Vec<(f32, Color)>,
+}
+
+impl ColorRamp {
+    /// Stops need ascending positions, there must be at least one
+    pub fn new(stops: Vec<(f32, Color)>) -> Result<ColorRamp, String> {
+        if stops.is_empty() {
+            return Err("expected at least one color".to_string());
+        }
+        if stops.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            return Err("expected ascending positions".to_string());
+        }
+        Ok(ColorRamp { stops })
+    }
+
+    /// Colors spread evenly over [0, 1]
+    pub fn even(colors: Vec<Color>) -> Result<ColorRamp, String> {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        ColorRamp::new(colors.into_iter().enumerate().map(|(i, color)| (i as f32 / last, color)).collect())
+    }
+
+    pub fn stops(&self) -> &[(f32, Color)] {
+        &self.stops
+    }
+
+    pub fn color(&self, t: f32) -> Color {
+        let next = self.stops.partition_point(|(position, _)| *position <= t);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let (start, start_color) = self.stops[next - 1];
+        let (end, end_color) = self.stops[next];
+        let s = (t - start) / (end - start);
+        start_color * (1.0 - s) + end_color * s
+    }
+}
+
+/// Solid texture: a pattern evaluated at the 3D hit point, colored through a ramp.
+/// Unlike image textures it needs no texture coordinates, so spheres are seamless.
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub space: TextureSpace,
+    /// Positions are multiplied by `scaling`, then shifted by `offset`
+    pub scaling: f64,
+    pub offset: Vector3,
+    /// Noise octaves of fBm, turbulence, marble and wood
+    pub octaves: u32,
+    /// How much noise bends marble veins and wood rings
+    pub distortion: f64,
+    pub ramp: ColorRamp,
+}
+
+impl Procedural {
+    /// Color at `position` (in the texture's space), `dp_dx` and `dp_dy` span the pixel footprint
+    pub fn color(&self, position: Point, dp_dx: Vector3, dp_dy: Vector3) -> Color {
+        let p = Vector3::from(position) * self.scaling + self.offset;
+        let value = match self.pattern {
+            Pattern::Noise => 0.5 + 0.5 * noise(p),
+            Pattern::Fbm => 0.5 + 0.5 * fbm(p, self.octaves),
+            Pattern::Turbulence => turbulence(p, self.octaves),
+            Pattern::Marble => 0.5 + 0.5 * (2.0 * PI * (p.x + self.distortion * turbulence(p, self.octaves))).sin(),
+            Pattern::Wood => {
+                let radius = p.x.hypot(p.z) + self.distortion * 0.25 * fbm(p, self.octaves);
+                radius - radius.floor()
+            }
+            Pattern::Worley => worley(p).0,
+            Pattern::Voronoi => worley(p).1,
+            Pattern::Checker => {
+                let width = |a: f64, b: f64| a.abs().max(b.abs()) * self.scaling;
+                let product = square_wave(p.x, width(dp_dx.x, dp_dy.x))
+                    * square_wave(p.y, width(dp_dx.y, dp_dy.y))
+                    * square_wave(p.z, width(dp_dx.z, dp_dy.z));
+                0.5 - 0.5 * product
+            }
+        };
+        self.ramp.color(value.clamp(0.0, 1.0) as f32)
+    }
+}
+
+/// Average over [x - width / 2, x + width / 2] of the wave that is 1 on even unit intervals and -1 on odd ones
+fn square_wave(x: f64, width: f64) -> f64 {
+    // the integral of the wave, a triangle wave
+    let integral = |x: f64| {
+        let phase = x - 2.0 * (x / 2.0).floor();
+        1.0 - (phase - 1.0).abs()
+    };
+    if width < 1e-9 {
+        if (x.floor() as i64).rem_euclid(2) == 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    } else {
+        (integral(x + width / 2.0) - integral(x - width / 2.0)) / width
+    }
+}
+
+/// Sum of octaves of noise, in about [-1, 1]
+fn fbm(p: Vector3, octaves: u32) -> f64 {
+    (0..octaves).map(|i| 0.5f64.powi(i as i32) * noise(p * 2f64.powi(i as i32))).sum::<f64>() * 0.5
+}
+
+/// Sum of octaves of the absolute noise, in about [0, 1]
+fn turbulence(p: Vector3, octaves: u32) -> f64 {
+    (0..octaves).map(|i| 0.5f64.powi(i as i32) * noise(p * 2f64.powi(i as i32)).abs()).sum::<f64>() * 0.5
+}
+
+/// Integer hash of a lattice point
+fn hash(x: i64, y: i64, z: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    // splitmix64 finalizer
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Number in [0, 1) from bits 11.. of a hash, `shift` picks other bits for further numbers
+fn unit(h: u64, shift: u32) -> f64 {
+    (h.rotate_right(shift) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Perlin's improved gradient noise, in about [-1, 1]
+/// https://mrl.cs.nyu.edu/~perlin/paper445.pdf
+pub fn noise(p: Vector3) -> f64 {
+    // the 12 edge directions of a cube, with 4 repeated to get 16
+    const GRADIENTS: [(f64, f64, f64); 16] = [
+        (1.0, 1.0, 0.0), (-1.0, 1.0, 0.0), (1.0, -1.0, 0.0), (-1.0, -1.0, 0.0),
+        (1.0, 0.0, 1.0), (-1.0, 0.0, 1.0), (1.0, 0.0, -1.0), (-1.0, 0.0, -1.0),
+        (0.0, 1.0, 1.0), (0.0, -1.0, 1.0), (0.0, 1.0, -1.0), (0.0, -1.0, -1.0),
+        (1.0, 1.0, 0.0), (-1.0, 1.0, 0.0), (0.0, -1.0, 1.0), (0.0, -1.0, -1.0),
+    ];
+    let (x0, y0, z0) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (fx, fy, fz) = (p.x - x0, p.y - y0, p.z - z0);
+    let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        let (gx, gy, gz) = GRADIENTS[(hash(x0 + dx, y0 + dy, z0 + dz) & 15) as usize];
+        gx * (fx - dx as f64) + gy * (fy - dy as f64) + gz * (fz - dz as f64)
+    };
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+    lerp(
+        w,
+        lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+        lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))),
+    )
+}
+
+/// Worley cellular noise with one jittered feature point per unit cell.
+/// Returns the distance to the nearest feature point and a random value of its cell.
+/// https://en.wikipedia.org/wiki/Worley_noise
+fn worley(p: Vector3) -> (f64, f64) {
+    let (x0, y0, z0) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let mut nearest = (f64::INFINITY, 0.0);
+    for z in z0 - 1..=z0 + 1 {
+        for y in y0 - 1..=y0 + 1 {
+            for x in x0 - 1..=x0 + 1 {
+                let h = hash(x, y, z);
+                let feature = Vector3 {
+                    x: x as f64 + unit(h, 0),
+                    y: y as f64 + unit(h, 21),
+                    z: z as f64 + unit(h, 42),
+                };
+                let distance = (feature - p).magnitude();
+                if distance < nearest.0 {
+                    nearest = (distance, unit(hash(h as i64, 0, 0), 0));
+                }
+            }
+        }
+    }
+    nearest
+}
@@ -1,49 +1,151 @@
 use core::f32;
+use std::f64::consts::PI;
 use std::mem::swap;
 use vector3::Vector3;
 
-use crate::object::{Object, Plane, Sphere};
+use crate::object::{
+    barycentric, interpolate_texture_coords, intersect_triangle, triangle_tangents, Object, Plane, Sphere, Triangle,
+    BARYCENTRIC_COORDS,
+};
 use crate::point::Point;
 use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::texture::TextureDerivatives;
 
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// Rays through the neighbouring pixels, for estimating the ray's footprint
+    pub differentials: Option<RayDifferentials>,
+    /// Wavelengths of spectral rendering, `None` for RGB
+    pub wavelengths: Option<Wavelengths>,
+}
+
+/// Offset rays of a ray: the rays one pixel to the right (x) and one pixel down (y)
+/// https://pbr-book.org/3ed-2018/Texture/Sampling_and_Antialiasing#FindingtheTextureSamplingRate
+#[derive(Clone, Copy)]
+pub struct RayDifferentials {
+    pub x_origin: Point,
+    pub x_direction: Vector3,
+    pub y_origin: Point,
+    pub y_direction: Vector3,
+}
+
+/// Changes of the hit point and of the surface normal between neighbouring pixels,
+/// zero for rays without differentials
+#[derive(Clone, Copy)]
+pub struct Footprint {
+    pub dp_dx: Vector3,
+    pub dp_dy: Vector3,
+    pub dn_dx: Vector3,
+    pub dn_dy: Vector3,
+}
+
+impl Default for Footprint {
+    fn default() -> Self {
+        Footprint { dp_dx: Vector3::zero(), dp_dy: Vector3::zero(), dn_dx: Vector3::zero(), dn_dy: Vector3::zero() }
+    }
 }
 
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        // TODO: fov adjustment
-        let fov_adjustment = 1.0f64;
+    /// Ray without differentials
+    pub fn new(origin: Point, direction: Vector3) -> Ray {
+        Ray { origin, direction, differentials: None, wavelengths: None }
+    }
+
+    /// Ray without differentials on from this one, with its wavelengths
+    pub fn next(&self, origin: Point, direction: Vector3) -> Ray {
+        Ray { origin, direction, differentials: None, wavelengths: self.wavelengths }
+    }
+
+    /// Camera ray through the point (x, y) of the image plane, measured in pixels
+    pub fn create_prime(x: f64, y: f64, scene: &Scene) -> Ray {
         let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x =
-            ((2.0 * (x as f64 + 0.5) / (scene.width as f64) - 1.0) * fov_adjustment) * aspect_ratio;
-        let sensor_y = (2.0 * (y as f64 + 0.5) / (scene.height as f64) - 1.0) * fov_adjustment;
-        // TODO:
-        // sensor_y *= -1
+        let direction = |x: f64, y: f64| {
+            let sensor_x = 2.0 * x / (scene.width as f64) - 1.0;
+            // image rows go top to bottom
+            let sensor_y = 1.0 - 2.0 * y / (scene.height as f64);
+            scene.camera.direction(sensor_x, sensor_y, aspect_ratio)
+        };
         Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
+            origin: scene.camera.position,
+            direction: direction(x, y),
+            differentials: Some(RayDifferentials {
+                x_origin: scene.camera.position,
+                x_direction: direction(x + 1.0, y),
+                y_origin: scene.camera.position,
+                y_direction: direction(x, y + 1.0),
+            }),
+            wavelengths: None,
+        }
+    }
+
+    /// Shrinks the differentials to a spacing of `scale` pixels, for pixels sampled several times
+    pub fn scale_differentials(&mut self, scale: f64) {
+        if let Some(differentials) = &mut self.differentials {
+            differentials.x_origin = self.origin + (Vector3::from(differentials.x_origin - self.origin) * scale).into();
+            differentials.x_direction = self.direction + (differentials.x_direction - self.direction) * scale;
+            differentials.y_origin = self.origin + (Vector3::from(differentials.y_origin - self.origin) * scale).into();
+            differentials.y_direction = self.direction + (differentials.y_direction - self.direction) * scale;
+        }
+    }
+
+    /// Where the offset rays hit the tangent plane of the hit point, and how the object's normal changes there
+    pub fn footprint(&self, object: &Object, hit_point: &Point, face: usize, surface_normal: &Vector3) -> Footprint {
+        let Some(differentials) = &self.differentials else {
+            return Footprint::default();
+        };
+        let plane_offset = |origin: &Point, direction: &Vector3| {
+            let t = Vector3::from(*hit_point - *origin).dot(surface_normal) / direction.dot(surface_normal);
+            let offset = Vector3::from(*origin - *hit_point) + *direction * t;
+            if t.is_finite() {
+                offset
+            } else {
+                Vector3::zero()
             }
-            .normalize(),
+        };
+        let dp_dx = plane_offset(&differentials.x_origin, &differentials.x_direction);
+        let dp_dy = plane_offset(&differentials.y_origin, &differentials.y_direction);
+        // the normal changes along with `surface_normal`, which may be flipped to face the ray
+        let sign = if object.surface_normal(hit_point, face).dot(surface_normal) < 0.0 { -1.0 } else { 1.0 };
+        let normal_at = |offset: &Vector3| object.surface_normal(&(*hit_point + (*offset).into()), face).normalize() * sign;
+        Footprint {
+            dp_dx,
+            dp_dy,
+            dn_dx: normal_at(&dp_dx) - *surface_normal,
+            dn_dy: normal_at(&dp_dy) - *surface_normal,
         }
     }
 
-    pub fn reflect(&self, hit_point: Point, surface_normal: Vector3) -> Ray {
+    pub fn reflect(&self, hit_point: Point, surface_normal: Vector3, footprint: &Footprint) -> Ray {
+        let direction = self.direction - (surface_normal * 2.0 * self.direction.dot(&surface_normal));
+        // the offset rays reflect off the hit point moved along the footprint, with a changed normal
+        let wo = Vector3::zero() - self.direction;
+        let differentials = self.differentials.map(|differentials| {
+            let offset = |dp: &Vector3, dn: &Vector3, offset_direction: &Vector3| {
+                let dwo = Vector3::zero() - *offset_direction - wo;
+                let d_cos = dwo.dot(&surface_normal) + wo.dot(dn);
+                (
+                    hit_point + (*dp).into(),
+                    direction - dwo + (*dn * wo.dot(&surface_normal) + surface_normal * d_cos) * 2.0,
+                )
+            };
+            let (x_origin, x_direction) = offset(&footprint.dp_dx, &footprint.dn_dx, &differentials.x_direction);
+            let (y_origin, y_direction) = offset(&footprint.dp_dy, &footprint.dn_dy, &differentials.y_direction);
+            RayDifferentials { x_origin, x_direction, y_origin, y_direction }
+        });
         Ray {
             origin: hit_point + (surface_normal * crate::SHADOW_BIAS).into(),
-            direction: self.direction
-                - (surface_normal * 2.0 * self.direction.dot(&surface_normal)),
+            direction,
+            differentials,
+            wavelengths: self.wavelengths,
         }
     }
 
-    /// Returns (cos_alpha, n1, n2) tuple
-    pub fn get_initial_data(&self, surface_normal: Vector3, index: f32) -> (f64, f64, f64) {
+    /// Returns (cos_alpha, n1, n2) tuple, `index` is the index inside the surface and `outside` the one outside it
+    pub fn get_initial_data(&self, surface_normal: Vector3, index: f32, outside: f32) -> (f64, f64, f64) {
         let cos_alpha = -self.direction.dot(&surface_normal);
-        let n1 = 1.0;
+        let n1 = outside as f64;
         let n2 = index as f64;
         if cos_alpha < 0.0 {
             // ray goes from inside to ouside
@@ -54,14 +156,24 @@ impl Ray {
         }
     }
 
-    pub fn refract(&self, hit_point: Point, surface_normal: Vector3, index: f32) -> Option<Ray> {
+    pub fn refract(
+        &self,
+        hit_point: Point,
+        surface_normal: Vector3,
+        index: f32,
+        outside: f32,
+        footprint: &Footprint,
+    ) -> Option<Ray> {
         let mut n = surface_normal;
+        let (mut dn_dx, mut dn_dy) = (footprint.dn_dx, footprint.dn_dy);
         let mut cos_alpha = -surface_normal.dot(&self.direction);
-        let mut n1 = 1.0;
+        let mut n1 = outside as f64;
         let mut n2 = index as f64;
         if cos_alpha < 0.0 {
             // inside the surface
             n = Vector3::zero() - n;
+            dn_dx = Vector3::zero() - dn_dx;
+            dn_dy = Vector3::zero() - dn_dy;
             swap(&mut n1, &mut n2);
             cos_alpha = -cos_alpha;
         } else {
@@ -84,20 +196,47 @@ impl Ray {
         let h2 = h1.normalize() * sin_beta;
         let r2 = v2 + h2;
 
-        // println!("{} vs {} ==> {} vs {} ===> {} vs {}", n1, n2, cos_alpha, cos_beta, sin_alpha, sin_beta);
+        // differentiating r2 = -eta wo + (eta cos_alpha - cos_beta) n, with sin_beta = eta sin_alpha
+        let eta = n2 / n1;
+        let wo = Vector3::zero() - self.direction;
+        let mu = eta * cos_alpha - cos_beta;
+        let differentials = self.differentials.map(|differentials| {
+            let offset = |dp: &Vector3, dn: &Vector3, offset_direction: &Vector3| {
+                let dwo = Vector3::zero() - *offset_direction - wo;
+                let d_cos = dwo.dot(&n) + wo.dot(dn);
+                let d_mu = (eta - eta * eta * cos_alpha / cos_beta) * d_cos;
+                (hit_point + (*dp).into(), r2 - dwo * eta + *dn * mu + n * d_mu)
+            };
+            let (x_origin, x_direction) = offset(&footprint.dp_dx, &dn_dx, &differentials.x_direction);
+            let (y_origin, y_direction) = offset(&footprint.dp_dy, &dn_dy, &differentials.y_direction);
+            RayDifferentials { x_origin, x_direction, y_origin, y_direction }
+        });
 
         Some(Ray {
             origin: hit_point - (n * crate::SHADOW_BIAS).into(),
             direction: r2,
+            differentials,
+            wavelengths: self.wavelengths,
         })
     }
 
+    /// The ray going on unchanged past the surface at the hit point, for surfaces that aren't there for it
+    pub fn pass_through(&self, hit_point: Point, surface_normal: Vector3) -> Ray {
+        let bias = if surface_normal.dot(&self.direction) < 0.0 { -crate::SHADOW_BIAS } else { crate::SHADOW_BIAS };
+        Ray {
+            origin: hit_point + (surface_normal * bias).into(),
+            direction: self.direction,
+            differentials: self.differentials,
+            wavelengths: self.wavelengths,
+        }
+    }
+
     /// Fresnel's equations
     /// https://en.wikipedia.org/wiki/Fresnel_equations
     /// return's total effective reflection coefficient R
     #[allow(dead_code)]
-    pub fn fresnel(&self, surface_normal: Vector3, index: f32) -> f64 {
-        let (cos_alpha, n1, n2) = self.get_initial_data(surface_normal, index);
+    pub fn fresnel(&self, surface_normal: Vector3, index: f32, outside: f32) -> f64 {
+        let (cos_alpha, n1, n2) = self.get_initial_data(surface_normal, index, outside);
         let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
         let sin_beta = n1 / n2 * sin_alpha;
 
@@ -109,9 +248,7 @@ impl Ray {
         let r_s = (n1 * cos_alpha - n2 * cos_beta) / (n1 * cos_alpha + n2 * cos_beta);
         let r_t = (n1 * cos_beta - n2 * cos_alpha) / (n1 * cos_beta + n2 * cos_alpha);
 
-        let r = 0.5 * (r_s * r_s + r_t * r_t);
-
-        r
+        0.5 * (r_s * r_s + r_t * r_t)
     }
 
     /// Shlick's approximation
@@ -119,42 +256,85 @@ impl Ray {
     /// return's total effective reflection coefficient R
     #[allow(dead_code)]
     pub fn schlicks(&self, surface_normal: Vector3, index: f32) -> f64 {
-        let (cos_alpha, n1, n2) = self.get_initial_data(surface_normal, index);
+        let (cos_alpha, n1, n2) = self.get_initial_data(surface_normal, index, 1.0);
         let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
         r0 + (1.0 - r0) * (1.0 - cos_alpha).powi(5)
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
 }
 
+/// Shapes rays can hit. Meshes are made of many faces, the other shapes of the single face 0.
 pub trait Intersectable {
-    fn intersect(&self, ray: &Ray) -> Option<f64>;
+    /// Distance to the nearest hit and the index of the hit face
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)>;
+
+    /// Texture coordinates of the hit point and their derivatives over the ray's footprint
+    fn texture_coords(&self, hit_point: &Point, face: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives);
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+    /// Derivatives of the position by the texture coordinates, (dp/du, dp/dv)
+    fn tangents(&self, hit_point: &Point, face: usize) -> (Vector3, Vector3);
 }
 
 impl Intersectable for Object {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)> {
         match self {
             Object::Sphere(sphere) => sphere.intersect(ray),
             Object::Plane(plane) => plane.intersect(ray),
+            Object::Triangle(triangle) => triangle.intersect(ray),
+            Object::Mesh(mesh) => mesh.intersect(ray),
         }
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, face: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives) {
         match self {
-            Self::Plane(plane) => plane.texture_coords(hit_point),
-            Self::Sphere(sphere) => sphere.texture_coords(hit_point),
+            Object::Sphere(sphere) => sphere.texture_coords(hit_point, face, footprint),
+            Object::Plane(plane) => plane.texture_coords(hit_point, face, footprint),
+            Object::Triangle(triangle) => triangle.texture_coords(hit_point, face, footprint),
+            Object::Mesh(mesh) => mesh.texture_coords(hit_point, face, footprint),
         }
     }
+
+    fn tangents(&self, hit_point: &Point, face: usize) -> (Vector3, Vector3) {
+        match self {
+            Object::Sphere(sphere) => sphere.tangents(hit_point, face),
+            Object::Plane(plane) => plane.tangents(hit_point, face),
+            Object::Triangle(triangle) => triangle.tangents(hit_point, face),
+            Object::Mesh(mesh) => mesh.tangents(hit_point, face),
+        }
+    }
+}
+
+/// Texture coordinates at the hit point, with derivatives from the coordinates at the ends of the footprint.
+/// `coords_at` has to extend the surface's parametrization around the hit point,
+/// differences in `x` are wrapped to [-0.5, 0.5] for parametrizations that wrap around.
+pub fn differentiate(
+    coords_at: impl Fn(&Point) -> TextureCoords,
+    hit_point: &Point,
+    footprint: &Footprint,
+    periodic_x: bool,
+) -> (TextureCoords, TextureDerivatives) {
+    let coords = coords_at(hit_point);
+    let difference = |dp: &Vector3| {
+        let other = coords_at(&(*hit_point + (*dp).into()));
+        let mut dx = other.x - coords.x;
+        if periodic_x {
+            dx -= dx.round();
+        }
+        (dx, other.y - coords.y)
+    };
+    let (du_dx, dv_dx) = difference(&footprint.dp_dx);
+    let (du_dy, dv_dy) = difference(&footprint.dp_dy);
+    (coords, TextureDerivatives { du_dx, dv_dx, du_dy, dv_dy })
 }
 
 impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
-        let l: Vector3 = Point::from(self.center.clone() - ray.origin.clone()).into();
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)> {
+        let l: Vector3 = (self.center - ray.origin).into();
         let adj = l.dot(&ray.direction);
         let d_sq = l.dot(&l) - adj * adj;
 
@@ -171,21 +351,43 @@ impl Intersectable for Sphere {
         let t0 = adj - inside;
         let t1 = adj + inside;
 
-        if t0 < 0.0 && t1 < 0.0 {
-            None
+        let distance = if t0 < 0.0 && t1 < 0.0 {
+            return None;
         } else if t0 < 0.0 {
-            Some(t1)
+            t1
         } else if t1 < 0.0 {
-            Some(t0)
+            t0
         } else {
-            let distance = if t0 < t1 { t0 } else { t1 };
-            Some(distance)
-        }
+            t0.min(t1)
+        };
+        Some((distance, 0))
+    }
 
-        // Some(adj - inside)
+    fn texture_coords(&self, hit_point: &Point, _: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives) {
+        // points next to the sphere are projected onto it
+        let coords_at = |point: &Point| {
+            let offset = Vector3::from(*point - self.center);
+            self.coords(&(self.center + (offset * (self.radius / offset.magnitude())).into()))
+        };
+        differentiate(coords_at, hit_point, footprint, true)
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn tangents(&self, hit_point: &Point, _: usize) -> (Vector3, Vector3) {
+        // u turns the azimuth once around y, v the polar angle from the top to the bottom
+        let offset = Vector3::from(*hit_point - self.center);
+        let azimuth = offset.z.atan2(offset.x);
+        let dp_du = Vector3 { x: -offset.z, y: 0.0, z: offset.x } * (2.0 * PI);
+        let dp_dv = Vector3 {
+            x: offset.y * azimuth.cos(),
+            y: -offset.x.hypot(offset.z),
+            z: offset.y * azimuth.sin(),
+        } * PI;
+        (dp_du, dp_dv)
+    }
+}
+
+impl Sphere {
+    fn coords(&self, hit_point: &Point) -> TextureCoords {
         let hit_vec = *hit_point - self.center;
         // TODO: figure out the formulas:
         // https://www.scratchapixel.com/lessons/mathematics-physics-for-computer-graphics/geometry/spherical-coordinates-and-trigonometric-functions.html
@@ -198,7 +400,7 @@ impl Intersectable for Sphere {
 }
 
 impl Intersectable for Plane {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)> {
         // TODO: figure this out
         // https://www.scratchapixel.com/lessons/3d-basic-rendering/minimal-ray-tracer-rendering-simple-shapes/ray-plane-and-ray-disk-intersection.html
         // ^^^
@@ -206,16 +408,28 @@ impl Intersectable for Plane {
         let normal = self.normal;
         let denom = normal.dot(&ray.direction);
         if denom > 1e-6 {
-            let v: Vector3 = (self.origin.clone() - ray.origin.clone()).into();
+            let v: Vector3 = (self.origin - ray.origin).into();
             let distance = v.dot(&normal) / denom;
             if distance >= 0.0 {
-                return Some(distance);
+                return Some((distance, 0));
             }
         }
         None
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, _: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives) {
+        differentiate(|point| self.coords(point), hit_point, footprint, false)
+    }
+
+    fn tangents(&self, _: &Point, _: usize) -> (Vector3, Vector3) {
+        let (x_axis, y_axis) = self.axes();
+        (x_axis / x_axis.dot(&x_axis), y_axis / y_axis.dot(&y_axis))
+    }
+}
+
+impl Plane {
+    /// Directions of the texture's x and y axes on the plane
+    fn axes(&self) -> (Vector3, Vector3) {
         let mut x_axis = self.normal.cross(&Vector3 {
             x: 0.0,
             y: 0.0,
@@ -229,7 +443,11 @@ impl Intersectable for Plane {
             });
         }
         let y_axis = self.normal.cross(&x_axis);
+        (x_axis, y_axis)
+    }
 
+    fn coords(&self, hit_point: &Point) -> TextureCoords {
+        let (x_axis, y_axis) = self.axes();
         let hit_vec = Vector3::from(*hit_point - self.origin);
 
         TextureCoords {
@@ -238,3 +456,32 @@ impl Intersectable for Plane {
         }
     }
 }
+
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<(f64, usize)> {
+        let [a, b, c] = self.vertices;
+        intersect_triangle(ray, a, b, c).map(|distance| (distance, 0))
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: usize, footprint: &Footprint) -> (TextureCoords, TextureDerivatives) {
+        differentiate(|point| self.coords(point), hit_point, footprint, false)
+    }
+
+    fn tangents(&self, _: &Point, _: usize) -> (Vector3, Vector3) {
+        triangle_tangents(self.vertices, self.texture_coords.unwrap_or(BARYCENTRIC_COORDS))
+    }
+}
+
+impl Triangle {
+    fn coords(&self, hit_point: &Point) -> TextureCoords {
+        let [a, b, c] = self.vertices;
+        let (w_a, w_b, w_c) = barycentric(hit_point, a, b, c);
+        match self.texture_coords {
+            Some(coords) => interpolate_texture_coords(&coords, (w_a, w_b, w_c)),
+            None => TextureCoords {
+                x: w_b as f32,
+                y: w_c as f32,
+            },
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use vector3::Vector3;
+
+/// Small PCG32 random number generator.
+/// Every pixel gets its own generator, so renders don't depend on the thread count.
+/// https://www.pcg-random.org/
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Generator for the pixel (x, y)
+    pub fn for_pixel(x: u32, y: u32) -> Rng {
+        Rng::new(((y as u64) << 32) | x as u64, 0x5851_f42d_4c95_7f2d)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(self.increment);
+        let xor_shifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    /// Uniform number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / (1u64 << 32) as f64
+    }
+}
+
+/// Sample positions inside the unit square, one jittered inside each cell of a grid with exactly `count` cells.
+/// The grid is as square as `count` allows, prime counts get strips of the square.
+pub fn stratified_samples(count: u32, rng: &mut Rng) -> Vec<(f64, f64)> {
+    let (columns, rows) = strata(count);
+    (0..count)
+        .map(|i| {
+            let x = ((i % columns) as f64 + rng.next_f64()) / columns as f64;
+            let y = ((i / columns) as f64 + rng.next_f64()) / rows as f64;
+            (x, y)
+        })
+        .collect()
+}
+
+/// Columns and rows of the grid of `stratified_samples`,
+/// the columns are the largest divisor of `count` not above its square root
+fn strata(count: u32) -> (u32, u32) {
+    let columns = (1..=(count as f64).sqrt() as u32).rev().find(|c| count.is_multiple_of(*c)).unwrap_or(1);
+    (columns, count / columns)
+}
+
+/// Cosine-weighted random direction on the hemisphere around the unit `normal`,
+/// its density is cos(theta) / pi
+pub fn cosine_hemisphere(normal: Vector3, rng: &mut Rng) -> Vector3 {
+    let (tangent, bitangent) = orthonormal_basis(normal);
+
+    // uniform point on the unit disk projected up onto the hemisphere
+    let r = rng.next_f64().sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.next_f64();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * z).normalize()
+}
+
+/// Two unit vectors completing the unit `normal` to an orthonormal basis
+pub fn orthonormal_basis(normal: Vector3) -> (Vector3, Vector3) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3 { x: 0.0, y: 1.0, z: 0.0 }
+    } else {
+        Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}
+
+/// Piecewise constant density over [0, 1) proportional to `function`
+pub struct Distribution1D {
+    function: Vec<f64>,
+    /// Running integral, normalized to end at 1
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f64>) -> Distribution1D {
+        let n = function.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + function[i].max(0.0) / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, value) in cdf.iter_mut().enumerate() {
+            // a zero function is sampled uniformly
+            *value = if integral > 0.0 { *value / integral } else { i as f64 / n as f64 };
+        }
+        Distribution1D { function, cdf, integral }
+    }
+
+    /// Maps a uniform `u` to (x in [0, 1), density at x, segment index)
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.function.len();
+        // last segment whose cdf starts at or before u
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        let x = ((i as f64 + offset) / n as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf(i), i)
+    }
+
+    /// Density of the segment `i`
+    pub fn pdf(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.function[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density over the unit square proportional to a row-major grid of values
+pub struct Distribution2D {
+    /// Distribution of x inside every row
+    rows: Vec<Distribution1D>,
+    /// Distribution of the rows
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = function
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Maps a uniform point to ((x, y) in the unit square, density there)
+    pub fn sample(&self, (u, v): (f64, f64)) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(v);
+        let (x, pdf, _) = self.rows[row].sample(u);
+        ((x, y), row_pdf * pdf)
+    }
+
+    /// Density at the point (x, y) of the unit square
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let columns = self.rows[row].function.len();
+        let column = ((x * columns as f64) as usize).min(columns - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_stratum_gets_one_sample() {
+        let mut rng = Rng::new(7, 3);
+        for count in 1..=50 {
+            let (columns, rows) = strata(count);
+            assert_eq!(columns * rows, count);
+            assert!(columns <= rows);
+            let mut hits = vec![0; count as usize];
+            for (x, y) in stratified_samples(count, &mut rng) {
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                let (column, row) = ((x * columns as f64) as u32, (y * rows as f64) as u32);
+                hits[(row * columns + column) as usize] += 1;
+            }
+            assert!(hits.iter().all(|&hit| hit == 1), "{} samples: {:?}", count, hits);
+        }
+    }
+
+    #[test]
+    fn grids_are_as_square_as_possible() {
+        assert_eq!(strata(3), (1, 3));
+        assert_eq!(strata(6), (2, 3));
+        assert_eq!(strata(8), (2, 4));
+        assert_eq!(strata(12), (3, 4));
+        assert_eq!(strata(16), (4, 4));
+        assert_eq!(strata(7), (1, 7));
+    }
+}
@@ -1,46 +1,180 @@
+use std::sync::OnceLock;
 use vector3::Vector3;
-use crate::{color::Color, object::Object, point::Point, rendering::{Intersectable, Ray}};
+use crate::{
+    bvh::{Aabb, Bvh},
+    camera::Camera,
+    color::{beer_lambert, Color},
+    environment::Environment,
+    filter::Filter,
+    ies::IesProfile,
+    medium::MediumStack,
+    object::Object,
+    point::Point,
+    rendering::{Intersectable, Ray},
+    sampling::orthonormal_basis,
+    tone_mapping::ToneMapping,
+    tracing::Integrator,
+    SHADOW_BIAS,
+};
 
 /// Scene definition
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub camera: Camera,
     pub objects: Vec<Object>,
     pub lights: Vec<Light>,
+    /// Light of rays that miss every object
+    pub environment: Environment,
+    pub integrator: Integrator,
+    /// Maximum number of bounces of a camera ray
     pub max_recursion_depth: u32,
+    pub samples_per_pixel: u32,
+    /// Reconstruction filter the samples are weighted with
+    pub filter: Filter,
+    /// Mapping of the rendered radiance to 8-bit images
+    pub tone_mapping: ToneMapping,
+    /// Number of rendering threads, 0 uses one thread per core
+    pub threads: usize,
+    /// Whether paths carry wavelengths instead of RGB, so dispersive materials split light into colors
+    pub spectral: bool,
+    /// Acceleration structure over `objects`, built on the first `trace`
+    pub accelerator: OnceLock<Accelerator>,
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.objects
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let accelerator = self.accelerator.get_or_init(|| Accelerator::build(&self.objects));
+
+        let bounded = accelerator
+            .bvh
+            .intersect(ray, |i| self.objects[accelerator.bounded[i]].intersect(ray))
+            .map(|(distance, i, face)| (distance, accelerator.bounded[i], face));
+        let unbounded = accelerator
+            .unbounded
+            .iter()
+            .filter_map(|&i| self.objects[i].intersect(ray).map(|(distance, face)| (distance, i, face)))
+            .filter(|(distance, _, _)| !distance.is_nan());
+
+        // ties go to the object that comes first in `objects`
+        bounded
+            .into_iter()
+            .chain(unbounded)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(a.1.cmp(&b.1)))
+            .map(|(distance, i, face)| Intersection::new(distance, &self.objects[i], face))
+    }
+
+    /// Nearest light whose surface the ray hits
+    pub fn trace_light(&self, ray: &Ray) -> Option<(f64, &Light)> {
+        self.lights
             .iter()
-            .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s)))
-            .filter(|i| !i.distance.is_nan())
-            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+            .filter_map(|light| light.intersect(ray).map(|distance| (distance, light)))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    /// Fraction of light travelling `distance` along the shadow ray, which starts in `media`: black behind opaque
+    /// objects, transparent ones let it through dimmed by `Object::transmittance` and their absorption, without bending it
+    pub fn transmittance(&self, ray: &Ray, distance: f64, media: MediumStack) -> Color {
+        let mut transmittance = Color::WHITE;
+        let mut ray = ray.next(ray.origin, ray.direction);
+        let mut media = media;
+        let mut remaining = distance;
+        while let Some(intersection) = self.trace(&ray) {
+            if intersection.distance >= remaining {
+                break;
+            }
+            transmittance = transmittance * beer_lambert(media.absorption(ray.wavelengths), intersection.distance);
+            let object = intersection.object;
+            let hit_point = ray.origin + (ray.direction * intersection.distance).into();
+            let normal = object.surface_normal(&hit_point, intersection.face).normalize();
+            let entering = normal.dot(&ray.direction) < 0.0;
+            // surfaces inside a higher priority medium are not there for the ray
+            if object.surface_type().index(None).is_none() || media.is_interface(object, entering) {
+                let outside = media.outside_index(object, ray.wavelengths);
+                transmittance = transmittance * object.transmittance(&hit_point, intersection.face, &ray, outside);
+            }
+            if transmittance.red.max(transmittance.green).max(transmittance.blue) <= 0.0 {
+                return Color::BLACK;
+            }
+            media = if entering { media.entered(object) } else { media.left(object) };
+            // continue on the far side of the surface
+            let bias = if normal.dot(&ray.direction) < 0.0 { -SHADOW_BIAS } else { SHADOW_BIAS };
+            let origin = hit_point + (normal * bias).into();
+            remaining -= Vector3::from(origin - ray.origin).dot(&ray.direction);
+            ray = ray.next(origin, ray.direction);
+        }
+        transmittance * beer_lambert(media.absorption(ray.wavelengths), remaining)
+    }
+}
+
+#[cfg(test)]
+impl Scene {
+    /// Empty 1x1 scene for tests to fill in, the camera sits at the origin looking down -z
+    pub fn empty() -> Scene {
+        Scene {
+            width: 1,
+            height: 1,
+            camera: Camera::new(
+                Point::zero(),
+                Point { x: 0.0, y: 0.0, z: -1.0 },
+                Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+                60.0,
+            ),
+            objects: vec![],
+            lights: vec![],
+            environment: Environment::Color(Color::BLACK),
+            integrator: Integrator::Whitted,
+            max_recursion_depth: 1,
+            samples_per_pixel: 1,
+            filter: Filter::new(crate::filter::FilterKind::Box),
+            tone_mapping: ToneMapping::new(crate::tone_mapping::ToneMapOperator::Clamp),
+            threads: 1,
+            spectral: false,
+            accelerator: OnceLock::new(),
+        }
     }
+}
+
+/// BVH over the bounded objects of a scene, unbounded ones (planes) are tested separately
+pub struct Accelerator {
+    bvh: Bvh,
+    /// Scene object index of every BVH primitive
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
 
-    // pub fn trace_for_light(&self, ray: &Ray) -> Option<Intersection> {
-    //     self.objects
-    //         .iter()
-    //         .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s)))
-    //         .filter(|i| !i.distance.is_nan())
-    //         .filter(|i| !i.object.surface_type().in_transparent())
-    //         .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
-    // }
+impl Accelerator {
+    pub fn build(objects: &[Object]) -> Accelerator {
+        let mut bounded = Vec::new();
+        let mut bounds: Vec<Aabb> = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            match object.bounds() {
+                Some(aabb) => {
+                    bounded.push(i);
+                    bounds.push(aabb);
+                }
+                None => unbounded.push(i),
+            }
+        }
+        Accelerator { bvh: Bvh::build(&bounds), bounded, unbounded }
+    }
 }
 
 /// Intersection struct
 pub struct Intersection<'a> {
     pub distance: f64,
     pub object: &'a Object,
+    /// Index of the hit face for meshes
+    pub face: usize,
 }
 
 impl Intersection<'_> {
-    fn new(distance: f64, object: &Object) -> Intersection {
+    fn new(distance: f64, object: &Object, face: usize) -> Intersection<'_> {
         Intersection {
-            distance: distance,
-            object: object,
+            distance,
+            object,
+            face,
         }
     }
 }
@@ -49,6 +183,9 @@ impl Intersection<'_> {
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
+    Rectangle(RectangleLight),
+    Disk(DiskLight),
+    Spot(SpotLight),
 }
 
 /// Far away parallel lightning struct
@@ -58,10 +195,66 @@ pub struct DirectionalLight {
     pub intensity: f32,
 }
 
-/// Spherical light sources
+/// Spherical light sources, a radius of 0 makes a point light
 pub struct SphericalLight {
     pub position: Point,
+    pub radius: f64,
+    pub color: Color,
+    /// Emitted power
+    pub intensity: f32,
+    /// Number of shadow rays per shaded point
+    pub samples: u32,
+}
+
+/// One-sided parallelogram light spanned by the edges `u` and `v`,
+/// emitting towards `u x v`
+pub struct RectangleLight {
+    pub center: Point,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub color: Color,
+    /// Emitted power
+    pub intensity: f32,
+    /// Number of shadow rays per shaded point
+    pub samples: u32,
+}
+
+/// One-sided disk light emitting towards `normal`
+pub struct DiskLight {
+    pub center: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub color: Color,
+    /// Emitted power
+    pub intensity: f32,
+    /// Number of shadow rays per shaded point
+    pub samples: u32,
+}
+
+/// Point light shining into a cone around `direction`, fading out smoothly between the inner and outer angles
+pub struct SpotLight {
+    pub position: Point,
+    pub direction: Vector3,
+    /// Angle from the axis out to which the light is at full strength, in degrees
+    pub inner_angle: f64,
+    /// Angle from the axis where the light has faded out, in degrees
+    pub outer_angle: f64,
+    /// Measured distribution of the light around the axis, its brightest direction gets the full intensity
+    pub profile: Option<IesProfile>,
+    /// Turn of the profile around the axis, in degrees
+    pub rotation: f64,
     pub color: Color,
+    /// Emitted power of a point light as bright as the spot at its brightest
+    pub intensity: f32,
+}
+
+/// Point on a light as seen from a shaded point
+pub struct LightSample {
+    /// Unit direction towards the light
+    pub direction: Vector3,
+    /// Distance to the sampled point, infinite for directional lights
+    pub distance: f64,
+    /// Irradiance on a surface facing the light, already divided by the sampling density
     pub intensity: f32,
 }
 
@@ -70,6 +263,221 @@ impl Light {
         match self {
             Light::Directional(light) => light.color,
             Light::Spherical(light) => light.color,
+            Light::Rectangle(light) => light.color,
+            Light::Disk(light) => light.color,
+            Light::Spot(light) => light.color,
         }
     }
-}
\ No newline at end of file
+
+    /// Number of shadow rays used for the light, point-like lights need only one
+    pub fn samples(&self) -> u32 {
+        match self {
+            Light::Directional(_) => 1,
+            Light::Spherical(light) if light.radius <= 0.0 => 1,
+            Light::Spherical(light) => light.samples.max(1),
+            Light::Rectangle(light) => light.samples.max(1),
+            Light::Disk(light) => light.samples.max(1),
+            Light::Spot(_) => 1,
+        }
+    }
+
+    /// Picks a point on the light from the unit square sample (s, t).
+    /// Returns `None` when the point can't be lit by the light.
+    pub fn sample(&self, hit_point: &Point, (s, t): (f64, f64)) -> Option<LightSample> {
+        match self {
+            Light::Directional(light) => Some(LightSample {
+                direction: Vector3::zero() - light.direction,
+                distance: f64::INFINITY,
+                intensity: light.intensity,
+            }),
+            Light::Spherical(light) => light.sample(hit_point, s, t),
+            Light::Rectangle(light) => {
+                let position = light.center + (light.u * (s - 0.5) + light.v * (t - 0.5)).into();
+                let normal = light.u.cross(&light.v);
+                let area = normal.magnitude();
+                area_sample(hit_point, position, normal * (1.0 / area), light.intensity)
+            }
+            Light::Disk(light) => {
+                let (tangent, bitangent) = orthonormal_basis(light.normal);
+                let r = light.radius * s.sqrt();
+                let phi = 2.0 * std::f64::consts::PI * t;
+                let position = light.center + (tangent * (r * phi.cos()) + bitangent * (r * phi.sin())).into();
+                area_sample(hit_point, position, light.normal, light.intensity)
+            }
+            Light::Spot(light) => light.sample(hit_point),
+        }
+    }
+
+    /// Distance along the ray to the light's surface, if it's hit from the emitting side
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        match self {
+            Light::Directional(_) | Light::Spot(_) => None,
+            Light::Spherical(light) => {
+                if light.radius <= 0.0 {
+                    return None;
+                }
+                let l: Vector3 = (light.position - ray.origin).into();
+                let adj = l.dot(&ray.direction);
+                let d_sq = l.dot(&l) - adj * adj;
+                let radius_sq = light.radius * light.radius;
+                if d_sq > radius_sq {
+                    return None;
+                }
+                let thc = (radius_sq - d_sq).sqrt();
+                let distance = if adj - thc > 0.0 { adj - thc } else { adj + thc };
+                (distance > 0.0).then_some(distance)
+            }
+            Light::Rectangle(light) => {
+                let normal = light.u.cross(&light.v).normalize();
+                let distance = plane_distance(ray, light.center, normal)?;
+                let offset: Vector3 = (ray.origin + (ray.direction * distance).into() - light.center).into();
+                // coordinates of the hit point along the edges
+                let (uu, uv, vv) = (light.u.dot(&light.u), light.u.dot(&light.v), light.v.dot(&light.v));
+                let (pu, pv) = (offset.dot(&light.u), offset.dot(&light.v));
+                let determinant = uu * vv - uv * uv;
+                let a = (pu * vv - pv * uv) / determinant;
+                let b = (pv * uu - pu * uv) / determinant;
+                (a.abs() <= 0.5 && b.abs() <= 0.5).then_some(distance)
+            }
+            Light::Disk(light) => {
+                let distance = plane_distance(ray, light.center, light.normal)?;
+                let hit_point = ray.origin + (ray.direction * distance).into();
+                ((hit_point - light.center).magnitude_sq() <= light.radius * light.radius).then_some(distance)
+            }
+        }
+    }
+
+    /// Emitted radiance, the color a camera sees when looking at the light
+    pub fn radiance(&self) -> Color {
+        let pi = std::f32::consts::PI;
+        let area = match self {
+            Light::Directional(_) | Light::Spot(_) => return Color::BLACK,
+            Light::Spherical(light) => 4.0 * pi * (light.radius * light.radius) as f32,
+            Light::Rectangle(light) => light.u.cross(&light.v).magnitude() as f32,
+            Light::Disk(light) => pi * (light.radius * light.radius) as f32,
+        };
+        if area <= 0.0 {
+            return Color::BLACK;
+        }
+        // a Lambertian emitter of power P and area A has the radiance P / (pi * A)
+        self.color() * (self.intensity() / (pi * area))
+    }
+
+    fn intensity(&self) -> f32 {
+        match self {
+            Light::Directional(light) => light.intensity,
+            Light::Spherical(light) => light.intensity,
+            Light::Rectangle(light) => light.intensity,
+            Light::Disk(light) => light.intensity,
+            Light::Spot(light) => light.intensity,
+        }
+    }
+}
+
+impl SphericalLight {
+    /// Samples the cone of directions the sphere covers,
+    /// points inside the sphere see it as a point light
+    fn sample(&self, hit_point: &Point, s: f64, t: f64) -> Option<LightSample> {
+        let to_center = Vector3::from(self.position - *hit_point);
+        let d_sq = to_center.dot(&to_center);
+        let d = d_sq.sqrt();
+        let axis = to_center * (1.0 / d);
+        if self.radius <= 0.0 || d <= self.radius {
+            return Some(LightSample {
+                direction: axis,
+                distance: d,
+                intensity: self.intensity / (4.0 * std::f32::consts::PI * (d_sq as f32)),
+            });
+        }
+
+        let cos_max = (1.0 - self.radius * self.radius / d_sq).max(0.0).sqrt();
+        let cos_theta = 1.0 - s * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * t;
+        let (tangent, bitangent) = orthonormal_basis(axis);
+        let direction =
+            (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta).normalize();
+
+        // distance to the near side of the sphere along the direction
+        let adj = d * cos_theta;
+        let distance = adj - (self.radius * self.radius - (d_sq - adj * adj)).max(0.0).sqrt();
+
+        // constant radiance integrated over the solid angle of the cone
+        let radiance = self.intensity / (4.0 * std::f32::consts::PI.powi(2) * (self.radius * self.radius) as f32);
+        let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_max) as f32;
+        Some(LightSample {
+            direction,
+            distance,
+            intensity: radiance * solid_angle,
+        })
+    }
+}
+
+impl SpotLight {
+    fn sample(&self, hit_point: &Point) -> Option<LightSample> {
+        let to_light = Vector3::from(self.position - *hit_point);
+        let distance_sq = to_light.dot(&to_light);
+        let distance = distance_sq.sqrt();
+        let direction = to_light * (1.0 / distance);
+        let emitted = Vector3::zero() - direction;
+        let strength = self.falloff(&emitted) * self.profile_intensity(&emitted);
+        if strength <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            intensity: self.intensity * strength / (4.0 * std::f32::consts::PI * distance_sq as f32),
+        })
+    }
+
+    /// Smoothstep of the cosine from the outer to the inner angle
+    fn falloff(&self, emitted: &Vector3) -> f32 {
+        let cos = emitted.dot(&self.direction);
+        let (cos_inner, cos_outer) = (self.inner_angle.to_radians().cos(), self.outer_angle.to_radians().cos());
+        if cos_inner <= cos_outer {
+            return if cos >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0) as f32;
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// The profile's vertical angles start at the axis, its horizontal ones at the axis' first tangent
+    fn profile_intensity(&self, emitted: &Vector3) -> f32 {
+        let Some(profile) = &self.profile else {
+            return 1.0;
+        };
+        let (tangent, bitangent) = orthonormal_basis(self.direction);
+        let vertical = emitted.dot(&self.direction).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = emitted.dot(&bitangent).atan2(emitted.dot(&tangent)).to_degrees() - self.rotation;
+        profile.intensity(vertical, horizontal)
+    }
+}
+
+/// Samples a point of a flat one-sided light, the density is uniform over its area
+fn area_sample(hit_point: &Point, position: Point, normal: Vector3, power: f32) -> Option<LightSample> {
+    let to_light = Vector3::from(position - *hit_point);
+    let distance_sq = to_light.dot(&to_light);
+    let distance = distance_sq.sqrt();
+    let direction = to_light * (1.0 / distance);
+    let cos_light = -direction.dot(&normal);
+    if cos_light <= 0.0 {
+        return None;
+    }
+    // radiance P / (pi * A) times the area over the squared distance
+    Some(LightSample {
+        direction,
+        distance,
+        intensity: power * cos_light as f32 / (std::f32::consts::PI * distance_sq as f32),
+    })
+}
+
+/// Distance to the plane through `origin`, only when the ray hits the side `normal` faces
+fn plane_distance(ray: &Ray, origin: Point, normal: Vector3) -> Option<f64> {
+    let denominator = ray.direction.dot(&normal);
+    if denominator >= -1e-9 {
+        return None;
+    }
+    let distance = Vector3::from(origin - ray.origin).dot(&normal) / denominator;
+    (distance > 0.0).then_some(distance)
+}
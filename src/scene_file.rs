@@ -0,0 +1,1566 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use image::{ImageError, ImageReader};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use toml::{Table, Value};
+use vector3::Vector3;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::environment::{Environment, EnvironmentMap};
+use crate::filter::{Filter, FilterKind};
+use crate::ies::{load_ies, IesError};
+use crate::obj::{load_obj, ObjError};
+use crate::object::{Bump, Coloration, Face, Material, Mesh, Object, Plane, Principled, Sphere, Surface, Triangle};
+use crate::point::Point;
+use crate::procedural::{ColorRamp, Procedural, TextureSpace};
+use crate::rendering::TextureCoords;
+use crate::scene::{DirectionalLight, DiskLight, Light, RectangleLight, Scene, SphericalLight, SpotLight};
+use crate::spectrum::Ior;
+use crate::texture::{Texture, TextureCache, TextureFilter, WrapMode};
+use crate::tone_mapping::{ToneMapOperator, ToneMapping};
+use crate::tracing::Integrator;
+use crate::ALBEDO;
+
+/// Errors of loading and saving scene description files
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, error: io::Error },
+    /// The file is not valid TOML
+    Syntax { path: PathBuf, line: usize, message: String },
+    /// Invalid or missing value, `key` is the path to it inside the file, e.g. `objects[2].radius`
+    Parse { path: PathBuf, key: String, message: String },
+    Image { path: PathBuf, key: String, error: ImageError },
+    Obj { key: String, error: ObjError },
+    Ies { key: String, error: IesError },
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            SceneError::Syntax { path, line, message } => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            }
+            SceneError::Parse { path, key, message } => {
+                write!(f, "{}: `{}`: {}", path.display(), key, message)
+            }
+            SceneError::Image { path, key, error } => {
+                write!(f, "`{}`: {}: {}", key, path.display(), error)
+            }
+            SceneError::Obj { key, error } => write!(f, "`{}`: {}", key, error),
+            SceneError::Ies { key, error } => write!(f, "`{}`: {}", key, error),
+            SceneError::Serialize(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Loads a TOML scene description.
+/// Texture, model and light profile paths are relative to the directory of the scene file.
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    load_scene_with_textures(path, &mut TextureCache::new())
+}
+
+/// Like `load_scene`, images already in `textures` are reused instead of being read again
+pub fn load_scene_with_textures(path: &Path, textures: &mut TextureCache) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(|error| SceneError::Io { path: path.to_path_buf(), error })?;
+    let table: Table = source.parse().map_err(|error: toml::de::Error| SceneError::Syntax {
+        path: path.to_path_buf(),
+        line: error.span().map_or(0, |span| source[..span.start].lines().count().max(1)),
+        message: error.message().to_string(),
+    })?;
+
+    let loader = Loader {
+        path,
+        directory: path.parent().unwrap_or(Path::new("")),
+        textures: RefCell::new(textures),
+    };
+    loader.scene(table)
+}
+
+/// Writes the scene out as a TOML scene description that `load_scene` reads back.
+/// Texture paths are rewritten relative to the directory of the new file where possible.
+pub fn save_scene(scene: &Scene, path: &Path) -> Result<(), SceneError> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let source = scene_to_toml(scene, directory)?;
+    fs::write(path, source).map_err(|error| SceneError::Io { path: path.to_path_buf(), error })
+}
+
+/// Serializes the scene, texture and light profile paths are written relative to `directory`
+pub fn scene_to_toml(scene: &Scene, directory: &Path) -> Result<String, SceneError> {
+    let description = SceneDesc {
+        render: RenderDesc {
+            width: scene.width,
+            height: scene.height,
+            integrator: scene.integrator.name().to_string(),
+            max_recursion_depth: scene.max_recursion_depth,
+            samples_per_pixel: scene.samples_per_pixel,
+            filter: scene.filter.kind.name().to_string(),
+            filter_radius: Some(scene.filter.radius),
+            tone_mapping: scene.tone_mapping.operator.name().to_string(),
+            exposure: f64_desc(scene.tone_mapping.exposure),
+            white_point: Some(f64_desc(scene.tone_mapping.white_point)),
+            threads: scene.threads,
+            spectral: scene.spectral,
+        },
+        camera: CameraDesc {
+            position: point_desc(&scene.camera.position),
+            look_at: point_desc(&scene.camera.look_at),
+            up: vector_desc(&scene.camera.up),
+            fov: scene.camera.fov,
+        },
+        materials: BTreeMap::new(),
+        objects: scene.objects.iter().map(|object| object_desc(object, directory)).collect::<Result<_, _>>()?,
+        lights: scene.lights.iter().map(|light| light_desc(light, directory)).collect(),
+        environment: environment_desc(&scene.environment, directory),
+    };
+    toml::to_string(&description).map_err(SceneError::Serialize)
+}
+
+// File layout: vvv
+// Tagged values (`{ type = "sphere", ... }`) are kept as raw `Value`s on load
+// and dispatched by hand, so errors inside them still get a precise key.
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc<O = Value, L = Value, E = Value> {
+    render: RenderDesc,
+    camera: CameraDesc,
+    /// Black when missing
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    environment: Option<E>,
+    /// Named materials, objects refer to them by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default = "Vec::new")]
+    objects: Vec<O>,
+    #[serde(default = "Vec::new")]
+    lights: Vec<L>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    width: u32,
+    height: u32,
+    #[serde(default = "default_integrator")]
+    integrator: String,
+    #[serde(default = "default_max_recursion_depth")]
+    max_recursion_depth: u32,
+    #[serde(default = "default_samples_per_pixel")]
+    samples_per_pixel: u32,
+    #[serde(default = "default_filter")]
+    filter: String,
+    /// Defaults to the radius of the chosen filter
+    #[serde(default)]
+    filter_radius: Option<f64>,
+    #[serde(default = "default_tone_mapping")]
+    tone_mapping: String,
+    /// In stops
+    #[serde(default)]
+    exposure: f64,
+    /// Defaults to the white point of the chosen operator
+    #[serde(default)]
+    white_point: Option<f64>,
+    #[serde(default)]
+    threads: usize,
+    /// Renders with wavelengths instead of RGB, for dispersion
+    #[serde(default)]
+    spectral: bool,
+}
+
+fn default_integrator() -> String {
+    Integrator::Whitted.name().to_string()
+}
+
+fn default_tone_mapping() -> String {
+    ToneMapOperator::Clamp.name().to_string()
+}
+
+fn default_max_recursion_depth() -> u32 {
+    5
+}
+
+fn default_samples_per_pixel() -> u32 {
+    1
+}
+
+fn default_filter() -> String {
+    FilterKind::Box.name().to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    position: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    fov: f64,
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ObjectDesc {
+    Sphere(SphereDesc),
+    Plane(PlaneDesc),
+    Triangle(TriangleDesc),
+    Mesh(MeshDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f64; 3],
+    radius: f64,
+    material: MaterialRef,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    origin: [f64; 3],
+    normal: [f64; 3],
+    material: MaterialRef,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [[f64; 3]; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normals: Option<[[f64; 3]; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture_coords: Option<[[f64; 2]; 3]>,
+    material: MaterialRef,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    vertices: Vec<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    normals: Vec<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    texture_coords: Vec<[f64; 2]>,
+    faces: Vec<FaceDesc>,
+    material: MaterialRef,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceDesc {
+    vertices: [usize; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normals: Option<[usize; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture_coords: Option<[usize; 3]>,
+}
+
+/// Wavefront OBJ model, `material` overrides the materials from its MTL files.
+/// Loaded models are written back as inline meshes.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjDesc {
+    path: PathBuf,
+    #[serde(default)]
+    material: Option<MaterialRef>,
+}
+
+/// Either the name of an entry in `materials` or an inline material
+#[derive(Serialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MaterialRefVisitor;
+
+        impl<'de> Visitor<'de> for MaterialRefVisitor {
+            type Value = MaterialRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a material name or a material table")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<MaterialRef, E> {
+                Ok(MaterialRef::Named(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MaterialRef, A::Error> {
+                MaterialDesc::deserialize(MapAccessDeserializer::new(map)).map(|material| MaterialRef::Inline(Box::new(material)))
+            }
+        }
+
+        deserializer.deserialize_any(MaterialRefVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    color: ColorationDesc,
+    #[serde(default = "default_albedo")]
+    albedo: f64,
+    /// `{ type = "diffusive" }` when missing
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    surface: Option<Value>,
+    /// Tangent space normal map, exclusive with `bump`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normal_map: Option<ColorationDesc>,
+    /// Height map, lifting the surface by up to `bump_strength` (1 when missing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bump: Option<ColorationDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bump_strength: Option<f64>,
+    /// Which of overlapping dielectrics fills their shared volume, the highest one does (0 when missing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<u32>,
+}
+
+fn default_albedo() -> f64 {
+    f64_desc(ALBEDO)
+}
+
+/// Either an RGB triple, a gray value, an image texture or a procedural texture.
+/// Tables are read as they are and told apart by the loader, which knows their key for errors.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ColorationDesc {
+    Color([f64; 3]),
+    Value(f64),
+    Table(Table),
+    Texture(TextureDesc),
+    Procedural(ProceduralDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    texture: PathBuf,
+    #[serde(default = "default_scaling")]
+    scaling: f64,
+    #[serde(default)]
+    offset: f64,
+    #[serde(default = "default_texture_filter")]
+    filter: String,
+    #[serde(default = "default_wrap")]
+    wrap: String,
+}
+
+fn default_scaling() -> f64 {
+    1.0
+}
+
+fn default_texture_filter() -> String {
+    TextureFilter::Trilinear.name().to_string()
+}
+
+fn default_wrap() -> String {
+    WrapMode::Repeat.name().to_string()
+}
+
+/// Solid texture, `colors` are spread evenly over the pattern's range unless `positions` are given
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProceduralDesc {
+    procedural: String,
+    #[serde(default = "default_texture_space")]
+    space: String,
+    #[serde(default = "default_scaling")]
+    scaling: f64,
+    #[serde(default)]
+    offset: [f64; 3],
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    #[serde(default = "default_distortion")]
+    distortion: f64,
+    #[serde(default = "default_ramp")]
+    colors: Vec<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    positions: Option<Vec<f64>>,
+}
+
+fn default_texture_space() -> String {
+    TextureSpace::Object.name().to_string()
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+fn default_distortion() -> f64 {
+    1.0
+}
+
+fn default_ramp() -> Vec<[f64; 3]> {
+    vec![[0.0; 3], [1.0; 3]]
+}
+
+impl<'de> Deserialize<'de> for ColorationDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ColorationVisitor;
+
+        impl<'de> Visitor<'de> for ColorationVisitor {
+            type Value = ColorationDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an [r, g, b] color, a number or a texture table")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<ColorationDesc, E> {
+                Ok(ColorationDesc::Value(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<ColorationDesc, E> {
+                Ok(ColorationDesc::Value(value as f64))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<ColorationDesc, A::Error> {
+                <[f64; 3]>::deserialize(SeqAccessDeserializer::new(seq)).map(ColorationDesc::Color)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ColorationDesc, A::Error> {
+                Table::deserialize(MapAccessDeserializer::new(map)).map(ColorationDesc::Table)
+            }
+        }
+
+        deserializer.deserialize_any(ColorationVisitor)
+    }
+}
+
+/// Diffusive surfaces are written by leaving `surface` out
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SurfaceDesc {
+    Reflective(ReflectiveDesc),
+    Refractive(RefractiveDesc),
+    Conductor(ConductorDesc),
+    RoughDielectric(RoughDielectricDesc),
+    Principled(Box<PrincipledDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiffusiveDesc {}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReflectiveDesc {
+    reflectivity: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RefractiveDesc {
+    transparency: f64,
+    /// A number or a dispersive index table
+    index: Value,
+    /// Per unit of length inside, per channel
+    #[serde(default, skip_serializing_if = "is_black")]
+    absorption: [f64; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConductorDesc {
+    roughness: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoughDielectricDesc {
+    roughness: f64,
+    index: Value,
+    #[serde(default, skip_serializing_if = "is_black")]
+    absorption: [f64; 3],
+}
+
+/// Indices of refraction varying with the wavelength, wavelengths in micrometers
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum IorDesc {
+    Cauchy(CauchyDesc),
+    Sellmeier(SellmeierDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CauchyDesc {
+    a: f64,
+    b: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SellmeierDesc {
+    b: [f64; 3],
+    c: [f64; 3],
+}
+
+fn is_black(rgb: &[f64; 3]) -> bool {
+    *rgb == [0.0; 3]
+}
+
+/// Parameters are numbers in [0, 1] or grayscale textures
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    #[serde(default = "default_zero")]
+    metallic: ColorationDesc,
+    #[serde(default = "default_half")]
+    roughness: ColorationDesc,
+    #[serde(default = "default_half")]
+    specular: ColorationDesc,
+    #[serde(default = "default_zero")]
+    clearcoat: ColorationDesc,
+    #[serde(default = "default_clearcoat_roughness")]
+    clearcoat_roughness: ColorationDesc,
+    #[serde(default = "default_zero")]
+    sheen: ColorationDesc,
+    #[serde(default = "default_zero")]
+    transmission: ColorationDesc,
+    #[serde(default = "default_principled_index")]
+    index: f64,
+}
+
+fn default_zero() -> ColorationDesc {
+    ColorationDesc::Value(0.0)
+}
+
+fn default_half() -> ColorationDesc {
+    ColorationDesc::Value(0.5)
+}
+
+fn default_clearcoat_roughness() -> ColorationDesc {
+    ColorationDesc::Value(0.03)
+}
+
+fn default_principled_index() -> f64 {
+    1.5
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum EnvironmentDesc {
+    Color(ColorEnvironmentDesc),
+    Gradient(GradientEnvironmentDesc),
+    Map(MapEnvironmentDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColorEnvironmentDesc {
+    color: [f64; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GradientEnvironmentDesc {
+    bottom: [f64; 3],
+    top: [f64; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapEnvironmentDesc {
+    /// Equirectangular image, relative to the scene file
+    path: PathBuf,
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+    /// Degrees around the y axis
+    #[serde(default)]
+    rotation: f64,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LightDesc {
+    Directional(DirectionalLightDesc),
+    Spherical(SphericalLightDesc),
+    Rectangle(RectangleLightDesc),
+    Disk(DiskLightDesc),
+    Spot(SpotLightDesc),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectionalLightDesc {
+    direction: [f64; 3],
+    color: [f64; 3],
+    intensity: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphericalLightDesc {
+    position: [f64; 3],
+    /// Point light when 0
+    #[serde(default)]
+    radius: f64,
+    color: [f64; 3],
+    intensity: f64,
+    #[serde(default = "default_light_samples")]
+    samples: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RectangleLightDesc {
+    center: [f64; 3],
+    u: [f64; 3],
+    v: [f64; 3],
+    color: [f64; 3],
+    intensity: f64,
+    #[serde(default = "default_light_samples")]
+    samples: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiskLightDesc {
+    center: [f64; 3],
+    normal: [f64; 3],
+    radius: f64,
+    color: [f64; 3],
+    intensity: f64,
+    #[serde(default = "default_light_samples")]
+    samples: u32,
+}
+
+/// Angles are in degrees from the axis. Without a profile the cone defaults to 30°,
+/// with one to the whole sphere. The inner angle defaults to the outer one, a hard edge.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpotLightDesc {
+    position: [f64; 3],
+    direction: [f64; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inner_angle: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    outer_angle: Option<f64>,
+    /// IESNA LM-63 photometric file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<PathBuf>,
+    /// Of the profile around the axis, in degrees
+    #[serde(default)]
+    rotation: f64,
+    color: [f64; 3],
+    intensity: f64,
+}
+
+fn default_light_samples() -> u32 {
+    16
+}
+
+// Description -> Scene: vvv
+
+/// Converts the parsed file into a `Scene`, loading textures and models on the way
+struct Loader<'a> {
+    path: &'a Path,
+    directory: &'a Path,
+    textures: RefCell<&'a mut TextureCache>,
+}
+
+impl Loader<'_> {
+    fn scene(&self, table: Table) -> Result<Scene, SceneError> {
+        let description: SceneDesc = self.parse(Value::Table(table), "")?;
+        if description.render.width == 0 {
+            return Err(self.error("render.width".to_string(), "expected a positive width".to_string()));
+        }
+        if description.render.height == 0 {
+            return Err(self.error("render.height".to_string(), "expected a positive height".to_string()));
+        }
+
+        let mut objects = Vec::new();
+        for (i, object) in description.objects.into_iter().enumerate() {
+            self.objects(object, &format!("objects[{}]", i), &description.materials, &mut objects)?;
+        }
+        let lights = description
+            .lights
+            .into_iter()
+            .enumerate()
+            .map(|(i, light)| self.light(light, &format!("lights[{}]", i)))
+            .collect::<Result<_, _>>()?;
+        let integrator = description
+            .render
+            .integrator
+            .parse()
+            .map_err(|message| self.error("render.integrator".to_string(), message))?;
+        let filter = self.filter(&description.render)?;
+        let environment = match description.environment {
+            Some(environment) => self.environment(environment, "environment")?,
+            None => Environment::Color(Color::BLACK),
+        };
+        let tone_mapping = self.tone_mapping(&description.render)?;
+        let fov = description.camera.fov;
+        if !(fov > 0.0 && fov < 180.0) {
+            return Err(self.error("camera.fov".to_string(), "expected an angle between 0 and 180°".to_string()));
+        }
+        let forward = Vector3::from(point(description.camera.look_at) - point(description.camera.position));
+        if forward.magnitude() == 0.0 {
+            let message = "expected a point away from the camera position".to_string();
+            return Err(self.error("camera.look_at".to_string(), message));
+        }
+        let up = vector(description.camera.up);
+        if up.magnitude() == 0.0 || forward.normalize().cross(&up.normalize()).magnitude() < 1e-9 {
+            let message = "expected a direction across the view direction".to_string();
+            return Err(self.error("camera.up".to_string(), message));
+        }
+
+        Ok(Scene {
+            width: description.render.width,
+            height: description.render.height,
+            camera: Camera::new(
+                point(description.camera.position),
+                point(description.camera.look_at),
+                vector(description.camera.up),
+                fov,
+            ),
+            objects,
+            lights,
+            environment,
+            integrator,
+            max_recursion_depth: description.render.max_recursion_depth,
+            samples_per_pixel: description.render.samples_per_pixel,
+            filter,
+            tone_mapping,
+            threads: description.render.threads,
+            spectral: description.render.spectral,
+            accelerator: OnceLock::new(),
+        })
+    }
+
+    fn filter(&self, render: &RenderDesc) -> Result<Filter, SceneError> {
+        let kind: FilterKind = render
+            .filter
+            .parse()
+            .map_err(|message| self.error("render.filter".to_string(), message))?;
+        let radius = render.filter_radius.unwrap_or(kind.default_radius());
+        if radius.is_nan() || radius <= 0.0 {
+            return Err(self.error("render.filter_radius".to_string(), "expected a positive radius".to_string()));
+        }
+        Ok(Filter { kind, radius })
+    }
+
+    fn tone_mapping(&self, render: &RenderDesc) -> Result<ToneMapping, SceneError> {
+        let operator: ToneMapOperator = render
+            .tone_mapping
+            .parse()
+            .map_err(|message| self.error("render.tone_mapping".to_string(), message))?;
+        let white_point = render.white_point.map_or(operator.default_white_point(), |white| white as f32);
+        if white_point.is_nan() || white_point <= 0.0 {
+            return Err(self.error("render.white_point".to_string(), "expected a positive white point".to_string()));
+        }
+        Ok(ToneMapping {
+            operator,
+            exposure: render.exposure as f32,
+            white_point,
+        })
+    }
+
+    fn error(&self, key: String, message: String) -> SceneError {
+        SceneError::Parse { path: self.path.to_path_buf(), key, message }
+    }
+
+    /// Deserializes the value found under `key`, errors point to the exact nested key
+    fn parse<T: DeserializeOwned>(&self, value: Value, key: &str) -> Result<T, SceneError> {
+        serde_path_to_error::deserialize(value).map_err(|error| {
+            let inner = error.path().to_string();
+            let key = match (key, inner.as_str()) {
+                (key, ".") => key.to_string(),
+                ("", inner) => inner.to_string(),
+                (key, inner) if inner.starts_with('[') => format!("{}{}", key, inner),
+                (key, inner) => format!("{}.{}", key, inner),
+            };
+            self.error(key, error.inner().message().to_string())
+        })
+    }
+
+    /// Splits a `{ type = "...", ... }` table into the type name and the other fields
+    fn tagged(&self, value: Value, key: &str) -> Result<(String, Value), SceneError> {
+        let Value::Table(mut table) = value else {
+            return Err(self.error(key.to_string(), "expected a table".to_string()));
+        };
+        match table.remove("type") {
+            Some(Value::String(kind)) => Ok((kind, Value::Table(table))),
+            Some(_) => Err(self.error(format!("{}.type", key), "expected a string".to_string())),
+            None => Err(self.error(key.to_string(), "missing field `type`".to_string())),
+        }
+    }
+
+    fn unknown_type(&self, key: &str, kind: &str, expected: &str) -> SceneError {
+        self.error(format!("{}.type", key), format!("unknown type `{}`, expected one of {}", kind, expected))
+    }
+
+    /// Pushes the objects made from the description, OBJ models can give several meshes
+    fn objects(
+        &self,
+        value: Value,
+        key: &str,
+        materials: &BTreeMap<String, MaterialDesc>,
+        objects: &mut Vec<Object>,
+    ) -> Result<(), SceneError> {
+        let material_key = format!("{}.material", key);
+        let material = |material: &MaterialRef| self.material_ref(material, &material_key, materials);
+
+        let (kind, value) = self.tagged(value, key)?;
+        match kind.as_str() {
+            "sphere" => {
+                let sphere: SphereDesc = self.parse(value, key)?;
+                if sphere.radius.is_nan() || sphere.radius <= 0.0 {
+                    return Err(self.error(format!("{}.radius", key), "expected a positive radius".to_string()));
+                }
+                objects.push(Object::Sphere(Sphere {
+                    center: point(sphere.center),
+                    radius: sphere.radius,
+                    material: material(&sphere.material)?,
+                }))
+            }
+            "plane" => {
+                let plane: PlaneDesc = self.parse(value, key)?;
+                let length = vector(plane.normal).magnitude();
+                if length.is_nan() || length == 0.0 {
+                    return Err(self.error(format!("{}.normal", key), "expected a nonzero normal".to_string()));
+                }
+                objects.push(Object::Plane(Plane {
+                    origin: point(plane.origin),
+                    normal: vector(plane.normal).normalize(),
+                    material: material(&plane.material)?,
+                }))
+            }
+            "triangle" => {
+                let triangle: TriangleDesc = self.parse(value, key)?;
+                objects.push(Object::Triangle(Triangle {
+                    vertices: triangle.vertices.map(point),
+                    normals: triangle.normals.map(|normals| normals.map(|n| vector(n).normalize())),
+                    texture_coords: triangle.texture_coords.map(|coords| coords.map(texture_coords_of)),
+                    material: material(&triangle.material)?,
+                }))
+            }
+            "mesh" => {
+                let mesh: MeshDesc = self.parse(value, key)?;
+                for (i, face) in mesh.faces.iter().enumerate() {
+                    let check = |name: &str, indices: Option<[usize; 3]>, count: usize| {
+                        match indices.and_then(|indices| indices.into_iter().find(|&i| i >= count)) {
+                            Some(index) => Err(self.error(
+                                format!("{}.faces[{}].{}", key, i, name),
+                                format!("index {} is out of range (have {})", index, count),
+                            )),
+                            None => Ok(()),
+                        }
+                    };
+                    check("vertices", Some(face.vertices), mesh.vertices.len())?;
+                    check("normals", face.normals, mesh.normals.len())?;
+                    check("texture_coords", face.texture_coords, mesh.texture_coords.len())?;
+                }
+                objects.push(Object::Mesh(Mesh::new(
+                    mesh.vertices.into_iter().map(point).collect(),
+                    mesh.normals.into_iter().map(|n| vector(n).normalize()).collect(),
+                    mesh.texture_coords.into_iter().map(texture_coords_of).collect(),
+                    mesh.faces
+                        .iter()
+                        .map(|face| Face {
+                            vertices: face.vertices,
+                            normals: face.normals,
+                            texture_coords: face.texture_coords,
+                        })
+                        .collect(),
+                    material(&mesh.material)?,
+                )))
+            }
+            "obj" => {
+                let model: ObjDesc = self.parse(value, key)?;
+                let meshes = load_obj(&self.directory.join(&model.path), &mut self.textures.borrow_mut())
+                    .map_err(|error| SceneError::Obj { key: format!("{}.path", key), error })?;
+                for object in meshes {
+                    match (object, &model.material) {
+                        (Object::Mesh(mut mesh), Some(model_material)) => {
+                            mesh.material = material(model_material)?;
+                            objects.push(Object::Mesh(mesh));
+                        }
+                        (object, _) => objects.push(object),
+                    }
+                }
+            }
+            kind => return Err(self.unknown_type(key, kind, "`sphere`, `plane`, `triangle`, `mesh`, `obj`")),
+        }
+        Ok(())
+    }
+
+    fn material_ref(
+        &self,
+        material: &MaterialRef,
+        key: &str,
+        materials: &BTreeMap<String, MaterialDesc>,
+    ) -> Result<Material, SceneError> {
+        match material {
+            MaterialRef::Named(name) => match materials.get(name) {
+                Some(description) => self.material(description, &format!("materials.{}", name)),
+                None => Err(self.error(key.to_string(), format!("unknown material `{}`", name))),
+            },
+            MaterialRef::Inline(description) => self.material(description, key),
+        }
+    }
+
+    fn material(&self, description: &MaterialDesc, key: &str) -> Result<Material, SceneError> {
+        let color = self.coloration(&description.color, &format!("{}.color", key), true)?;
+        let surface = match &description.surface {
+            Some(surface) => self.surface(surface.clone(), &format!("{}.surface", key))?,
+            None => Surface::Diffusive,
+        };
+        let bump = match (&description.normal_map, &description.bump) {
+            (Some(_), Some(_)) => {
+                return Err(self.error(format!("{}.bump", key), "expected either `normal_map` or `bump`".to_string()))
+            }
+            (Some(map), None) => Some(Bump::Normal(self.coloration(map, &format!("{}.normal_map", key), false)?)),
+            (None, Some(height)) => Some(Bump::Height {
+                height: self.coloration(height, &format!("{}.bump", key), false)?,
+                strength: description.bump_strength.unwrap_or(1.0) as f32,
+            }),
+            (None, None) => None,
+        };
+        Ok(Material {
+            color,
+            albedo: description.albedo as f32,
+            surface,
+            bump,
+            priority: description.priority.unwrap_or(0),
+        })
+    }
+
+    /// `srgb` is false for textures holding data instead of colors
+    fn coloration(&self, description: &ColorationDesc, key: &str, srgb: bool) -> Result<Coloration, SceneError> {
+        match description {
+            ColorationDesc::Color(rgb) => Ok(Coloration::Color(color(*rgb))),
+            ColorationDesc::Value(value) => Ok(Coloration::Color(color([*value; 3]))),
+            ColorationDesc::Table(table) if table.contains_key("procedural") => {
+                self.procedural(&self.parse(Value::Table(table.clone()), key)?, key)
+            }
+            ColorationDesc::Table(table) => self.texture(&self.parse(Value::Table(table.clone()), key)?, key, srgb),
+            ColorationDesc::Texture(texture) => self.texture(texture, key, srgb),
+            ColorationDesc::Procedural(procedural) => self.procedural(procedural, key),
+        }
+    }
+
+    fn texture(&self, texture: &TextureDesc, key: &str, srgb: bool) -> Result<Coloration, SceneError> {
+        let path = self.directory.join(&texture.texture);
+        let image = self.textures.borrow_mut().load(&path, srgb).map_err(|error| SceneError::Image {
+            path: path.clone(),
+            key: format!("{}.texture", key),
+            error,
+        })?;
+        let mut image_texture = Texture::new(image);
+        image_texture.filter = texture
+            .filter
+            .parse()
+            .map_err(|message| self.error(format!("{}.filter", key), message))?;
+        image_texture.wrap = texture.wrap.parse().map_err(|message| self.error(format!("{}.wrap", key), message))?;
+        Ok(Coloration::Texture {
+            texture: image_texture,
+            scaling: texture.scaling as f32,
+            offset: texture.offset as f32,
+        })
+    }
+
+    fn procedural(&self, procedural: &ProceduralDesc, key: &str) -> Result<Coloration, SceneError> {
+        let pattern = procedural
+            .procedural
+            .parse()
+            .map_err(|message| self.error(format!("{}.procedural", key), message))?;
+        let space = procedural.space.parse().map_err(|message| self.error(format!("{}.space", key), message))?;
+        if !(1..=16).contains(&procedural.octaves) {
+            return Err(self.error(format!("{}.octaves", key), "expected between 1 and 16 octaves".to_string()));
+        }
+        let colors = procedural.colors.iter().map(|rgb| color(*rgb)).collect::<Vec<_>>();
+        let ramp = match &procedural.positions {
+            None => ColorRamp::even(colors).map_err(|message| self.error(format!("{}.colors", key), message))?,
+            Some(positions) if positions.len() != colors.len() => {
+                return Err(self.error(
+                    format!("{}.positions", key),
+                    format!("expected {} positions, one per color", colors.len()),
+                ))
+            }
+            Some(positions) => {
+                let stops = positions.iter().map(|position| *position as f32).zip(colors).collect();
+                ColorRamp::new(stops).map_err(|message| self.error(format!("{}.positions", key), message))?
+            }
+        };
+        Ok(Coloration::Procedural(Box::new(Procedural {
+            pattern,
+            space,
+            scaling: procedural.scaling,
+            offset: vector(procedural.offset),
+            octaves: procedural.octaves,
+            distortion: procedural.distortion,
+            ramp,
+        })))
+    }
+
+    fn environment(&self, value: Value, key: &str) -> Result<Environment, SceneError> {
+        let (kind, value) = self.tagged(value, key)?;
+        match kind.as_str() {
+            "color" => {
+                let environment: ColorEnvironmentDesc = self.parse(value, key)?;
+                Ok(Environment::Color(color(environment.color)))
+            }
+            "gradient" => {
+                let environment: GradientEnvironmentDesc = self.parse(value, key)?;
+                Ok(Environment::Gradient { bottom: color(environment.bottom), top: color(environment.top) })
+            }
+            "map" => {
+                let environment: MapEnvironmentDesc = self.parse(value, key)?;
+                let path = self.directory.join(&environment.path);
+                let image_error = |error| SceneError::Image {
+                    path: path.clone(),
+                    key: format!("{}.path", key),
+                    error,
+                };
+                let image = ImageReader::open(&path)
+                    .map_err(|error| image_error(ImageError::IoError(error)))?
+                    .decode()
+                    .map_err(image_error)?;
+                Ok(Environment::Map(EnvironmentMap::new(
+                    &image,
+                    path.display().to_string(),
+                    environment.intensity as f32,
+                    environment.rotation,
+                )))
+            }
+            kind => Err(self.unknown_type(key, kind, "`color`, `gradient`, `map`")),
+        }
+    }
+
+    fn surface(&self, value: Value, key: &str) -> Result<Surface, SceneError> {
+        let (kind, value) = self.tagged(value, key)?;
+        match kind.as_str() {
+            "diffusive" => {
+                self.parse::<DiffusiveDesc>(value, key)?;
+                Ok(Surface::Diffusive)
+            }
+            "reflective" => {
+                let surface: ReflectiveDesc = self.parse(value, key)?;
+                Ok(Surface::Reflective { reflectivity: surface.reflectivity as f32 })
+            }
+            "refractive" => {
+                let surface: RefractiveDesc = self.parse(value, key)?;
+                Ok(Surface::Refractive {
+                    transparency: surface.transparency as f32,
+                    index: self.index(surface.index, key)?,
+                    absorption: self.absorption(surface.absorption, key)?,
+                })
+            }
+            "conductor" => {
+                let surface: ConductorDesc = self.parse(value, key)?;
+                Ok(Surface::Conductor { roughness: self.roughness(surface.roughness, key)? })
+            }
+            "rough_dielectric" => {
+                let surface: RoughDielectricDesc = self.parse(value, key)?;
+                Ok(Surface::RoughDielectric {
+                    roughness: self.roughness(surface.roughness, key)?,
+                    index: self.index(surface.index, key)?,
+                    absorption: self.absorption(surface.absorption, key)?,
+                })
+            }
+            "principled" => {
+                let surface: PrincipledDesc = self.parse(value, key)?;
+                let parameter = |description: &ColorationDesc, name: &str| {
+                    self.coloration(description, &format!("{}.{}", key, name), false)
+                };
+                Ok(Surface::Principled(Box::new(Principled {
+                    metallic: parameter(&surface.metallic, "metallic")?,
+                    roughness: parameter(&surface.roughness, "roughness")?,
+                    specular: parameter(&surface.specular, "specular")?,
+                    clearcoat: parameter(&surface.clearcoat, "clearcoat")?,
+                    clearcoat_roughness: parameter(&surface.clearcoat_roughness, "clearcoat_roughness")?,
+                    sheen: parameter(&surface.sheen, "sheen")?,
+                    transmission: parameter(&surface.transmission, "transmission")?,
+                    index: surface.index as f32,
+                })))
+            }
+            kind => Err(self.unknown_type(
+                key,
+                kind,
+                "`diffusive`, `reflective`, `refractive`, `conductor`, `rough_dielectric`, `principled`",
+            )),
+        }
+    }
+
+    fn roughness(&self, roughness: f64, key: &str) -> Result<f32, SceneError> {
+        if !(0.0..=1.0).contains(&roughness) {
+            return Err(self.error(format!("{}.roughness", key), "expected a roughness between 0 and 1".to_string()));
+        }
+        Ok(roughness as f32)
+    }
+
+    /// A number, or a `cauchy` or `sellmeier` table for dispersive materials
+    fn index(&self, value: Value, key: &str) -> Result<Ior, SceneError> {
+        let key = format!("{}.index", key);
+        let index = match value {
+            Value::Table(_) => {
+                let (kind, value) = self.tagged(value, &key)?;
+                match kind.as_str() {
+                    "cauchy" => {
+                        let cauchy: CauchyDesc = self.parse(value, &key)?;
+                        Ior::Cauchy { a: cauchy.a as f32, b: cauchy.b as f32 }
+                    }
+                    "sellmeier" => {
+                        let sellmeier: SellmeierDesc = self.parse(value, &key)?;
+                        Ior::Sellmeier { b: sellmeier.b.map(|b| b as f32), c: sellmeier.c.map(|c| c as f32) }
+                    }
+                    kind => return Err(self.unknown_type(&key, kind, "`cauchy`, `sellmeier`")),
+                }
+            }
+            value => Ior::Constant(self.parse::<f64>(value, &key)? as f32),
+        };
+        if !index.is_valid() {
+            return Err(self.error(key, "expected a positive index over the visible range".to_string()));
+        }
+        Ok(index)
+    }
+
+    fn absorption(&self, absorption: [f64; 3], key: &str) -> Result<Color, SceneError> {
+        if absorption.iter().any(|coefficient| *coefficient < 0.0) {
+            return Err(self.error(format!("{}.absorption", key), "expected non-negative coefficients".to_string()));
+        }
+        Ok(color(absorption))
+    }
+
+    fn light(&self, value: Value, key: &str) -> Result<Light, SceneError> {
+        let (kind, value) = self.tagged(value, key)?;
+        match kind.as_str() {
+            "directional" => {
+                let light: DirectionalLightDesc = self.parse(value, key)?;
+                Ok(Light::Directional(DirectionalLight {
+                    direction: vector(light.direction).normalize(),
+                    color: color(light.color),
+                    intensity: light.intensity as f32,
+                }))
+            }
+            "spherical" => {
+                let light: SphericalLightDesc = self.parse(value, key)?;
+                Ok(Light::Spherical(SphericalLight {
+                    position: point(light.position),
+                    radius: light.radius,
+                    color: color(light.color),
+                    intensity: light.intensity as f32,
+                    samples: light.samples,
+                }))
+            }
+            "rectangle" => {
+                let light: RectangleLightDesc = self.parse(value, key)?;
+                if vector(light.u).cross(&vector(light.v)).magnitude() == 0.0 {
+                    return Err(self.error(format!("{}.v", key), "the edges `u` and `v` must not be parallel".to_string()));
+                }
+                Ok(Light::Rectangle(RectangleLight {
+                    center: point(light.center),
+                    u: vector(light.u),
+                    v: vector(light.v),
+                    color: color(light.color),
+                    intensity: light.intensity as f32,
+                    samples: light.samples,
+                }))
+            }
+            "disk" => {
+                let light: DiskLightDesc = self.parse(value, key)?;
+                Ok(Light::Disk(DiskLight {
+                    center: point(light.center),
+                    normal: vector(light.normal).normalize(),
+                    radius: light.radius,
+                    color: color(light.color),
+                    intensity: light.intensity as f32,
+                    samples: light.samples,
+                }))
+            }
+            "spot" => {
+                let light: SpotLightDesc = self.parse(value, key)?;
+                let profile = match &light.profile {
+                    Some(path) => Some(
+                        load_ies(&self.directory.join(path))
+                            .map_err(|error| SceneError::Ies { key: format!("{}.profile", key), error })?,
+                    ),
+                    None => None,
+                };
+                let outer_angle = light.outer_angle.unwrap_or(if profile.is_some() { 180.0 } else { 30.0 });
+                let inner_angle = light.inner_angle.unwrap_or(outer_angle);
+                if !(0.0..=180.0).contains(&outer_angle) {
+                    return Err(self.error(format!("{}.outer_angle", key), "expected an angle in [0, 180]".to_string()));
+                }
+                if !(0.0..=outer_angle).contains(&inner_angle) {
+                    return Err(self.error(
+                        format!("{}.inner_angle", key),
+                        "expected an angle between 0 and `outer_angle`".to_string(),
+                    ));
+                }
+                Ok(Light::Spot(SpotLight {
+                    position: point(light.position),
+                    direction: vector(light.direction).normalize(),
+                    inner_angle,
+                    outer_angle,
+                    profile,
+                    rotation: light.rotation,
+                    color: color(light.color),
+                    intensity: light.intensity as f32,
+                }))
+            }
+            kind => Err(self.unknown_type(key, kind, "`directional`, `spherical`, `rectangle`, `disk`, `spot`")),
+        }
+    }
+}
+
+fn point([x, y, z]: [f64; 3]) -> Point {
+    Point { x, y, z }
+}
+
+fn vector([x, y, z]: [f64; 3]) -> Vector3 {
+    Vector3 { x, y, z }
+}
+
+fn color([red, green, blue]: [f64; 3]) -> Color {
+    Color { red: red as f32, green: green as f32, blue: blue as f32 }
+}
+
+fn texture_coords_of([x, y]: [f64; 2]) -> TextureCoords {
+    TextureCoords { x: x as f32, y: y as f32 }
+}
+
+// Scene -> Description: vvv
+
+fn object_desc(object: &Object, directory: &Path) -> Result<ObjectDesc, SceneError> {
+    Ok(match object {
+        Object::Sphere(sphere) => ObjectDesc::Sphere(SphereDesc {
+            center: point_desc(&sphere.center),
+            radius: sphere.radius,
+            material: material_desc(&sphere.material, directory)?,
+        }),
+        Object::Plane(plane) => ObjectDesc::Plane(PlaneDesc {
+            origin: point_desc(&plane.origin),
+            normal: vector_desc(&plane.normal),
+            material: material_desc(&plane.material, directory)?,
+        }),
+        Object::Triangle(triangle) => ObjectDesc::Triangle(TriangleDesc {
+            vertices: triangle.vertices.map(|v| point_desc(&v)),
+            normals: triangle.normals.map(|normals| normals.map(|n| vector_desc(&n))),
+            texture_coords: triangle.texture_coords.map(|coords| coords.map(|c| texture_coords_desc(&c))),
+            material: material_desc(&triangle.material, directory)?,
+        }),
+        Object::Mesh(mesh) => ObjectDesc::Mesh(MeshDesc {
+            vertices: mesh.vertices.iter().map(point_desc).collect(),
+            normals: mesh.normals.iter().map(vector_desc).collect(),
+            texture_coords: mesh.texture_coords.iter().map(texture_coords_desc).collect(),
+            faces: mesh
+                .faces
+                .iter()
+                .map(|face| FaceDesc {
+                    vertices: face.vertices,
+                    normals: face.normals,
+                    texture_coords: face.texture_coords,
+                })
+                .collect(),
+            material: material_desc(&mesh.material, directory)?,
+        }),
+    })
+}
+
+/// Textures are written relative to `directory`, constants as RGB triples or as numbers if `scalar`
+fn coloration_desc(coloration: &Coloration, scalar: bool, directory: &Path) -> ColorationDesc {
+    match coloration {
+        Coloration::Color(c) if scalar => ColorationDesc::Value(f64_desc(c.red)),
+        Coloration::Color(c) => ColorationDesc::Color(color_desc(c)),
+        Coloration::Texture { texture, scaling, offset } => ColorationDesc::Texture(TextureDesc {
+            texture: relative_path(Path::new(&texture.image.path), directory),
+            scaling: f64_desc(*scaling),
+            offset: f64_desc(*offset),
+            filter: texture.filter.name().to_string(),
+            wrap: texture.wrap.name().to_string(),
+        }),
+        Coloration::Procedural(procedural) => {
+            let stops = procedural.ramp.stops();
+            let even = ColorRamp::even(stops.iter().map(|(_, color)| *color).collect())
+                .is_ok_and(|ramp| ramp.stops().iter().zip(stops).all(|(a, b)| a.0 == b.0));
+            ColorationDesc::Procedural(ProceduralDesc {
+                procedural: procedural.pattern.name().to_string(),
+                space: procedural.space.name().to_string(),
+                scaling: procedural.scaling,
+                offset: vector_desc(&procedural.offset),
+                octaves: procedural.octaves,
+                distortion: procedural.distortion,
+                colors: stops.iter().map(|(_, color)| color_desc(color)).collect(),
+                positions: (!even).then(|| stops.iter().map(|(position, _)| f64_desc(*position)).collect()),
+            })
+        }
+    }
+}
+
+fn material_desc(material: &Material, directory: &Path) -> Result<MaterialRef, SceneError> {
+    let color = coloration_desc(&material.color, false, directory);
+    let surface = match material.surface {
+        Surface::Diffusive => None,
+        Surface::Reflective { reflectivity } => Some(SurfaceDesc::Reflective(ReflectiveDesc {
+            reflectivity: f64_desc(reflectivity),
+        })),
+        Surface::Refractive { transparency, index, absorption } => Some(SurfaceDesc::Refractive(RefractiveDesc {
+            transparency: f64_desc(transparency),
+            index: index_desc(&index)?,
+            absorption: color_desc(&absorption),
+        })),
+        Surface::Conductor { roughness } => Some(SurfaceDesc::Conductor(ConductorDesc {
+            roughness: f64_desc(roughness),
+        })),
+        Surface::RoughDielectric { roughness, index, absorption } => {
+            Some(SurfaceDesc::RoughDielectric(RoughDielectricDesc {
+                roughness: f64_desc(roughness),
+                index: index_desc(&index)?,
+                absorption: color_desc(&absorption),
+            }))
+        }
+        Surface::Principled(ref principled) => {
+            let parameter = |coloration| coloration_desc(coloration, true, directory);
+            Some(SurfaceDesc::Principled(Box::new(PrincipledDesc {
+                metallic: parameter(&principled.metallic),
+                roughness: parameter(&principled.roughness),
+                specular: parameter(&principled.specular),
+                clearcoat: parameter(&principled.clearcoat),
+                clearcoat_roughness: parameter(&principled.clearcoat_roughness),
+                sheen: parameter(&principled.sheen),
+                transmission: parameter(&principled.transmission),
+                index: f64_desc(principled.index),
+            })))
+        }
+    };
+    let (normal_map, bump, bump_strength) = match &material.bump {
+        Some(Bump::Normal(map)) => (Some(coloration_desc(map, false, directory)), None, None),
+        Some(Bump::Height { height, strength }) => {
+            (None, Some(coloration_desc(height, true, directory)), Some(f64_desc(*strength)))
+        }
+        None => (None, None, None),
+    };
+    let surface = surface.map(Value::try_from).transpose().map_err(SceneError::Serialize)?;
+    Ok(MaterialRef::Inline(Box::new(MaterialDesc {
+        color,
+        albedo: f64_desc(material.albedo),
+        surface,
+        normal_map,
+        bump,
+        bump_strength,
+        priority: (material.priority != 0).then_some(material.priority),
+    })))
+}
+
+fn environment_desc(environment: &Environment, directory: &Path) -> Option<EnvironmentDesc> {
+    if environment.is_black() {
+        return None;
+    }
+    Some(match environment {
+        Environment::Color(c) => EnvironmentDesc::Color(ColorEnvironmentDesc { color: color_desc(c) }),
+        Environment::Gradient { bottom, top } => EnvironmentDesc::Gradient(GradientEnvironmentDesc {
+            bottom: color_desc(bottom),
+            top: color_desc(top),
+        }),
+        Environment::Map(map) => EnvironmentDesc::Map(MapEnvironmentDesc {
+            path: relative_path(Path::new(&map.path), directory),
+            intensity: f64_desc(map.intensity),
+            rotation: map.rotation,
+        }),
+    })
+}
+
+fn light_desc(light: &Light, directory: &Path) -> LightDesc {
+    match light {
+        Light::Directional(light) => LightDesc::Directional(DirectionalLightDesc {
+            direction: vector_desc(&light.direction),
+            color: color_desc(&light.color),
+            intensity: f64_desc(light.intensity),
+        }),
+        Light::Spherical(light) => LightDesc::Spherical(SphericalLightDesc {
+            position: point_desc(&light.position),
+            radius: light.radius,
+            color: color_desc(&light.color),
+            intensity: f64_desc(light.intensity),
+            samples: light.samples,
+        }),
+        Light::Rectangle(light) => LightDesc::Rectangle(RectangleLightDesc {
+            center: point_desc(&light.center),
+            u: vector_desc(&light.u),
+            v: vector_desc(&light.v),
+            color: color_desc(&light.color),
+            intensity: f64_desc(light.intensity),
+            samples: light.samples,
+        }),
+        Light::Disk(light) => LightDesc::Disk(DiskLightDesc {
+            center: point_desc(&light.center),
+            normal: vector_desc(&light.normal),
+            radius: light.radius,
+            color: color_desc(&light.color),
+            intensity: f64_desc(light.intensity),
+            samples: light.samples,
+        }),
+        Light::Spot(light) => LightDesc::Spot(SpotLightDesc {
+            position: point_desc(&light.position),
+            direction: vector_desc(&light.direction),
+            inner_angle: Some(light.inner_angle),
+            outer_angle: Some(light.outer_angle),
+            profile: light.profile.as_ref().map(|profile| relative_path(Path::new(&profile.path), directory)),
+            rotation: light.rotation,
+            color: color_desc(&light.color),
+            intensity: f64_desc(light.intensity),
+        }),
+    }
+}
+
+/// Widens `f32` through its shortest decimal form, so `0.8f32` is written as `0.8`
+fn f64_desc(value: f32) -> f64 {
+    value.to_string().parse().unwrap()
+}
+
+fn index_desc(index: &Ior) -> Result<Value, SceneError> {
+    let description = match *index {
+        Ior::Constant(index) => return Ok(Value::Float(f64_desc(index))),
+        Ior::Cauchy { a, b } => IorDesc::Cauchy(CauchyDesc { a: f64_desc(a), b: f64_desc(b) }),
+        Ior::Sellmeier { b, c } => IorDesc::Sellmeier(SellmeierDesc { b: b.map(f64_desc), c: c.map(f64_desc) }),
+    };
+    Value::try_from(description).map_err(SceneError::Serialize)
+}
+
+fn point_desc(point: &Point) -> [f64; 3] {
+    [point.x, point.y, point.z]
+}
+
+fn vector_desc(vector: &Vector3) -> [f64; 3] {
+    [vector.x, vector.y, vector.z]
+}
+
+fn color_desc(color: &Color) -> [f64; 3] {
+    [f64_desc(color.red), f64_desc(color.green), f64_desc(color.blue)]
+}
+
+fn texture_coords_desc(coords: &TextureCoords) -> [f64; 2] {
+    [f64_desc(coords.x), f64_desc(coords.y)]
+}
+
+/// Expresses `path` relative to `directory` when both exist, otherwise returns it unchanged
+fn relative_path(path: &Path, directory: &Path) -> PathBuf {
+    let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+    let (Ok(path), Ok(directory)) = (path.canonicalize(), directory.canonicalize()) else {
+        return path.to_path_buf();
+    };
+    let path_components: Vec<Component> = path.components().collect();
+    let directory_components: Vec<Component> = directory.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&directory_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..directory_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Scene file viewing down -z from the origin at 4x4 pixels, `extra` is appended after its `[camera]` table
+#[cfg(test)]
+pub fn minimal_scene_toml(extra: &str) -> String {
+    format!(
+        "[render]\nwidth = 4\nheight = 4\n\n[camera]\nposition = [0.0, 0.0, 0.0]\n\
+         look_at = [0.0, 0.0, -1.0]\nup = [0.0, 1.0, 0.0]\nfov = 60.0\n\n{}",
+        extra
+    )
+}
+
+/// Loads `source` as a scene file in the temporary directory, the paths in it are relative to that
+#[cfg(test)]
+pub fn load_scene_source(source: &str, textures: &mut TextureCache) -> Result<Scene, SceneError> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("raytracer-{}-{}.toml", std::process::id(), FILES.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    fs::write(&path, source).unwrap();
+    let scene = load_scene_with_textures(&path, textures);
+    fs::remove_file(&path).unwrap();
+    scene
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_survives_a_round_trip() {
+        let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/example.toml");
+        let scene = load_scene(&example).unwrap();
+        let path = std::env::temp_dir().join(format!("raytracer-{}-example.toml", std::process::id()));
+        save_scene(&scene, &path).unwrap();
+        let reloaded = load_scene(&path);
+        fs::remove_file(&path).unwrap();
+        let reloaded = reloaded.unwrap();
+
+        assert_eq!(reloaded.objects.len(), scene.objects.len());
+        assert_eq!(reloaded.lights.len(), scene.lights.len());
+        let directory = example.parent().unwrap();
+        assert_eq!(scene_to_toml(&reloaded, directory).unwrap(), scene_to_toml(&scene, directory).unwrap());
+    }
+
+    #[test]
+    fn field_of_view_out_of_range_is_rejected() {
+        for fov in ["0.0", "180.0", "-30.0", "nan"] {
+            let source = minimal_scene_toml("").replace("fov = 60.0", &format!("fov = {}", fov));
+            match load_scene_source(&source, &mut TextureCache::new()) {
+                Err(SceneError::Parse { key, .. }) => assert_eq!(key, "camera.fov"),
+                Err(error) => panic!("fov = {}: unexpected error {}", fov, error),
+                Ok(_) => panic!("fov = {} was accepted", fov),
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_values_are_rejected() {
+        let object = |fields: &str| {
+            minimal_scene_toml(&format!("[[objects]]\n{}\nmaterial = {{ color = [1.0, 1.0, 1.0] }}\n", fields))
+        };
+        let cases = [
+            (minimal_scene_toml("").replace("width = 4", "width = 0"), "render.width"),
+            (minimal_scene_toml("").replace("height = 4", "height = 0"), "render.height"),
+            (object("type = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = 0.0"), "objects[0].radius"),
+            (object("type = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = -1.0"), "objects[0].radius"),
+            (object("type = \"plane\"\norigin = [0.0, -1.0, 0.0]\nnormal = [0.0, 0.0, 0.0]"), "objects[0].normal"),
+            (minimal_scene_toml("").replace("-1.0]\nup", "0.0]\nup"), "camera.look_at"),
+            (minimal_scene_toml("").replace("up = [0.0, 1.0, 0.0]", "up = [0.0, 0.0, 0.0]"), "camera.up"),
+            // parallel to the view direction
+            (minimal_scene_toml("").replace("up = [0.0, 1.0, 0.0]", "up = [0.0, 0.0, 2.0]"), "camera.up"),
+        ];
+        for (source, expected) in cases {
+            match load_scene_source(&source, &mut TextureCache::new()) {
+                Err(SceneError::Parse { key, .. }) => assert_eq!(key, expected),
+                Err(error) => panic!("{}: unexpected error {}", expected, error),
+                Ok(_) => panic!("{} was accepted", expected),
+            }
+        }
+    }
+}
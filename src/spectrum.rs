@@ -0,0 +1,219 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+
+// Spectral rendering: every path carries three wavelengths in the channels of its colors.
+// RGB colors of the scene are turned into spectra where the path meets them,
+// and the values the path ends up with are turned back into RGB through CIE XYZ.
+
+/// Wavelengths in nanometers the visible range is sampled in
+const MIN_WAVELENGTH: f32 = 360.0;
+const MAX_WAVELENGTH: f32 = 830.0;
+
+/// Wavelength indices of refraction are quoted at (the helium d line), used by RGB rendering
+const REFERENCE_WAVELENGTH: f32 = 587.6;
+
+/// Index of refraction, constant or varying with the wavelength
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Ior {
+    Constant(f32),
+    /// Cauchy's equation n = a + b / λ², λ in micrometers
+    Cauchy { a: f32, b: f32 },
+    /// Sellmeier equation n² = 1 + Σ b λ² / (λ² - c), λ in micrometers
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    /// Index at `wavelength` in nanometers
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers * micrometers;
+        match *self {
+            Ior::Constant(index) => index,
+            Ior::Cauchy { a, b } => a + b / squared,
+            Ior::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * squared / (squared - c[i])).sum::<f32>()).sqrt()
+            }
+        }
+    }
+
+    /// Index for the path's hero wavelength, or at the reference wavelength for RGB rendering
+    pub fn index(&self, wavelengths: Option<Wavelengths>) -> f32 {
+        self.at(wavelengths.map_or(REFERENCE_WAVELENGTH, |wavelengths| wavelengths.hero()))
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    /// Whether the index is a positive number over the visible range
+    pub fn is_valid(&self) -> bool {
+        (MIN_WAVELENGTH as u32..=MAX_WAVELENGTH as u32)
+            .step_by(10)
+            .all(|wavelength| self.at(wavelength as f32).is_finite() && self.at(wavelength as f32) > 0.0)
+    }
+}
+
+/// Wavelengths a spectral path carries, one per color channel: the hero wavelength leads the path,
+/// the others ride along with it until a dispersive surface sends them their own ways.
+/// https://doi.org/10.1111/cgf.12419
+#[derive(Clone, Copy)]
+pub struct Wavelengths {
+    /// In nanometers
+    pub nanometers: [f32; 3],
+    /// Densities the wavelengths were picked with, 0 for wavelengths no longer followed
+    pdfs: [f32; 3],
+}
+
+impl Wavelengths {
+    /// Three stratified wavelengths for a uniform `u`, denser where the eye is more sensitive
+    pub fn sample(u: f64) -> Wavelengths {
+        let mut nanometers = [0.0; 3];
+        let mut pdfs = [0.0; 3];
+        for i in 0..3 {
+            let u = (u + i as f64 / 3.0).fract();
+            nanometers[i] = sample_visible(u);
+            pdfs[i] = visible_pdf(nanometers[i]);
+        }
+        Wavelengths { nanometers, pdfs }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.nanometers[0]
+    }
+
+    /// Drops the wavelengths other than the hero one, for paths through dispersive surfaces.
+    /// Returns the weight of the path's channels from there on.
+    pub fn terminate_secondary(&mut self) -> Color {
+        if self.pdfs[1] == 0.0 && self.pdfs[2] == 0.0 {
+            return Color::WHITE;
+        }
+        self.pdfs = [self.pdfs[0] / 3.0, 0.0, 0.0];
+        Color { red: 3.0, green: 0.0, blue: 0.0 }
+    }
+
+    /// Values of the RGB color's spectrum at the wavelengths, in the channels
+    pub fn color(&self, color: Color) -> Color {
+        Color {
+            red: upsample(color, self.nanometers[0]),
+            green: upsample(color, self.nanometers[1]),
+            blue: upsample(color, self.nanometers[2]),
+        }
+    }
+
+    /// Linear sRGB estimate of the spectrum whose values at the wavelengths are in the channels of `values`,
+    /// scaled so a flat spectrum of 1 comes out white
+    pub fn to_rgb(&self, values: Color) -> Color {
+        let values = [values.red, values.green, values.blue];
+        let mut rgb = Color::BLACK;
+        for (i, value) in values.into_iter().enumerate() {
+            if self.pdfs[i] > 0.0 {
+                rgb += rgb_matching(self.nanometers[i]) * (value / (3.0 * self.pdfs[i]));
+            }
+        }
+        rgb
+    }
+}
+
+/// Colors seen by a path: the color itself for RGB rendering, its spectrum at the path's wavelengths otherwise
+pub fn at(color: Color, wavelengths: Option<Wavelengths>) -> Color {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.color(color),
+        None => color,
+    }
+}
+
+/// Inverse of the CDF of the density `visible_pdf`
+/// https://pbr-book.org/4ed/Radiometry,_Spectra,_and_Color/Color#SampleVisibleWavelengths
+fn sample_visible(u: f64) -> f32 {
+    (538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()) as f32
+}
+
+/// Density over [360, 830] nm roughly following the luminous efficiency of the eye
+fn visible_pdf(wavelength: f32) -> f32 {
+    if !(MIN_WAVELENGTH..=MAX_WAVELENGTH).contains(&wavelength) {
+        return 0.0;
+    }
+    0.003939804 / (0.0072 * (wavelength - 538.0)).cosh().powi(2)
+}
+
+/// CIE 1931 2° color matching functions, multi-lobe Gaussian fit
+/// https://jcgt.org/published/0002/02/01/
+fn cie_xyz(wavelength: f32) -> [f32; 3] {
+    let lobe = |mean: f32, below: f32, above: f32| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+/// Linear sRGB of a unit of light at `wavelength`, white balanced so that a flat spectrum of 1 comes out white
+fn rgb_matching(wavelength: f32) -> Color {
+    static WHITE: OnceLock<[f32; 3]> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let mut sum = [0.0; 3];
+        for wavelength in MIN_WAVELENGTH as u32..=MAX_WAVELENGTH as u32 {
+            let rgb = xyz_to_rgb(cie_xyz(wavelength as f32));
+            (0..3).for_each(|i| sum[i] += rgb[i]);
+        }
+        sum
+    });
+    let rgb = xyz_to_rgb(cie_xyz(wavelength));
+    Color { red: rgb[0] / white[0], green: rgb[1] / white[1], blue: rgb[2] / white[2] }
+}
+
+/// CIE XYZ to linear sRGB (D65)
+fn xyz_to_rgb([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+/// Smits' basis spectra, sampled in 10 bins over [380, 720] nm
+/// https://doi.org/10.1080/10867651.1999.10487511
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Value at `wavelength` of a smooth spectrum with the RGB color, Smits' method: the gray part
+/// is white, what is left over is made of the spectra of the two secondary and primary colors it lies between
+fn upsample(color: Color, wavelength: f32) -> f32 {
+    // linear between the bin centers
+    let bin = ((wavelength - 380.0) / 34.0 - 0.5).clamp(0.0, 9.0);
+    let (i, t) = (bin.floor() as usize, bin.fract());
+    let basis = |spectrum: &[f32; 10]| spectrum[i] * (1.0 - t) + spectrum[(i + 1).min(9)] * t;
+
+    let Color { red, green, blue } = color;
+    if red <= green && red <= blue {
+        red * basis(&SMITS_WHITE)
+            + if green <= blue {
+                (green - red) * basis(&SMITS_CYAN) + (blue - green) * basis(&SMITS_BLUE)
+            } else {
+                (blue - red) * basis(&SMITS_CYAN) + (green - blue) * basis(&SMITS_GREEN)
+            }
+    } else if green <= red && green <= blue {
+        green * basis(&SMITS_WHITE)
+            + if red <= blue {
+                (red - green) * basis(&SMITS_MAGENTA) + (blue - red) * basis(&SMITS_BLUE)
+            } else {
+                (blue - green) * basis(&SMITS_MAGENTA) + (red - blue) * basis(&SMITS_RED)
+            }
+    } else {
+        blue * basis(&SMITS_WHITE)
+            + if red <= green {
+                (red - blue) * basis(&SMITS_YELLOW) + (green - red) * basis(&SMITS_GREEN)
+            } else {
+                (green - blue) * basis(&SMITS_YELLOW) + (red - green) * basis(&SMITS_RED)
+            }
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use image::{ColorType, DynamicImage, ImageError, ImageReader};
+
+use crate::color::{srgb_decode, Color};
+use crate::rendering::TextureCoords;
+
+/// Reconstruction of texture colors between and across texels
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextureFilter {
+    /// Closest texel of the full resolution image
+    Nearest,
+    /// Blend of the 4 closest texels
+    Bilinear,
+    /// Bilinear lookups in the two mip levels matching the footprint size, blended
+    Trilinear,
+    /// Elliptically weighted average over the anisotropic footprint
+    Ewa,
+}
+
+impl TextureFilter {
+    pub const NAMES: &str = "`nearest`, `bilinear`, `trilinear`, `ewa`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureFilter::Nearest => "nearest",
+            TextureFilter::Bilinear => "bilinear",
+            TextureFilter::Trilinear => "trilinear",
+            TextureFilter::Ewa => "ewa",
+        }
+    }
+}
+
+impl FromStr for TextureFilter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "nearest" => Ok(TextureFilter::Nearest),
+            "bilinear" => Ok(TextureFilter::Bilinear),
+            "trilinear" => Ok(TextureFilter::Trilinear),
+            "ewa" => Ok(TextureFilter::Ewa),
+            _ => Err(format!("unknown texture filter `{}`, expected one of {}", name, TextureFilter::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for TextureFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Addressing of texels outside of the image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WrapMode {
+    /// The image tiles the plane
+    Repeat,
+    /// Edge texels extend outwards
+    Clamp,
+    /// The image tiles the plane, every other copy flipped
+    Mirror,
+}
+
+impl WrapMode {
+    pub const NAMES: &str = "`repeat`, `clamp`, `mirror`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WrapMode::Repeat => "repeat",
+            WrapMode::Clamp => "clamp",
+            WrapMode::Mirror => "mirror",
+        }
+    }
+
+    /// Texel index of the column or row `i` of an image `size` texels wide
+    fn index(&self, i: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+impl FromStr for WrapMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            "mirror" => Ok(WrapMode::Mirror),
+            _ => Err(format!("unknown wrap mode `{}`, expected one of {}", name, WrapMode::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for WrapMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Footprint of a pixel on the texture: derivatives of the texture coordinates
+/// along the image's x and y axes. Zero derivatives select the full resolution image.
+#[derive(Clone, Copy, Default)]
+pub struct TextureDerivatives {
+    pub du_dx: f32,
+    pub dv_dx: f32,
+    pub du_dy: f32,
+    pub dv_dy: f32,
+}
+
+/// Longest ratio between the major and minor axes of EWA footprints,
+/// longer ellipses are widened to keep the number of texels bounded
+const MAX_ANISOTROPY: f32 = 8.0;
+
+/// Falloff of the Gaussian EWA filter
+const EWA_ALPHA: f32 = 2.0;
+
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+}
+
+impl MipLevel {
+    /// Half resolution copy, each texel averaging a 2x2 block
+    fn downsample(&self) -> MipLevel {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::BLACK;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let source_x = (2 * x + dx).min(self.width - 1);
+                    let source_y = (2 * y + dy).min(self.height - 1);
+                    sum += self.texels[(source_y * self.width + source_x) as usize];
+                }
+                texels.push(sum * 0.25);
+            }
+        }
+        MipLevel { width, height, texels }
+    }
+}
+
+/// Decoded image with its mip pyramid, shared by every texture showing it.
+/// Texture coordinates (0, 0) and (1, 1) are the top left and bottom right corners.
+pub struct TextureImage {
+    /// File the image was loaded from
+    pub path: String,
+    /// Full resolution image first, each next level half the size down to 1x1
+    levels: Vec<MipLevel>,
+}
+
+impl TextureImage {
+    /// `srgb` images hold colors and are decoded to linear values, the others hold data like
+    /// roughness and are taken as they are. Float images (.hdr, .exr) are always linear.
+    pub fn new(image: &DynamicImage, path: String, srgb: bool) -> TextureImage {
+        let decode = srgb && !matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let image = image.to_rgb32f();
+        let (width, height) = image.dimensions();
+        let texels = image
+            .pixels()
+            .map(|pixel| {
+                let [red, green, blue] = if decode { pixel.0.map(srgb_decode) } else { pixel.0 };
+                Color { red, green, blue }
+            })
+            .collect();
+
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while let Some(last) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            levels.push(last.downsample());
+        }
+
+        TextureImage { path, levels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+}
+
+/// Loads every image file once and hands out shared references to it.
+/// Images are kept per path and color space, as sRGB decoding happens when loading.
+#[derive(Default)]
+pub struct TextureCache {
+    images: HashMap<(PathBuf, bool), Arc<TextureImage>>,
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache::default()
+    }
+
+    pub fn load(&mut self, path: &Path, srgb: bool) -> Result<Arc<TextureImage>, ImageError> {
+        let key = (path.to_path_buf(), srgb);
+        if let Some(image) = self.images.get(&key) {
+            return Ok(Arc::clone(image));
+        }
+        let decoded = ImageReader::open(path).map_err(ImageError::IoError)?.decode()?;
+        let image = Arc::new(TextureImage::new(&decoded, path.display().to_string(), srgb));
+        self.images.insert(key, Arc::clone(&image));
+        Ok(image)
+    }
+
+    /// Registers an in-memory image, later loads of `path` return it without touching the file system
+    pub fn insert(&mut self, path: &Path, image: &DynamicImage, srgb: bool) -> Arc<TextureImage> {
+        let image = Arc::new(TextureImage::new(image, path.display().to_string(), srgb));
+        self.images.insert((path.to_path_buf(), srgb), Arc::clone(&image));
+        image
+    }
+}
+
+/// Filtered lookups into a shared image
+#[derive(Clone)]
+pub struct Texture {
+    pub image: Arc<TextureImage>,
+    pub filter: TextureFilter,
+    pub wrap: WrapMode,
+}
+
+impl Texture {
+    pub fn new(image: Arc<TextureImage>) -> Texture {
+        Texture { image, filter: TextureFilter::Trilinear, wrap: WrapMode::Repeat }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    pub fn lookup(&self, coords: TextureCoords, derivatives: &TextureDerivatives) -> Color {
+        match self.filter {
+            TextureFilter::Nearest => {
+                let level = &self.image.levels[0];
+                let x = (coords.x * level.width as f32).floor() as i64;
+                let y = (coords.y * level.height as f32).floor() as i64;
+                self.texel(0, x, y)
+            }
+            TextureFilter::Bilinear => self.bilinear(0, coords),
+            TextureFilter::Trilinear => {
+                // the widest side of the footprint, in texels of the full resolution image
+                let (width, height) = (self.width() as f32, self.height() as f32);
+                let footprint = (derivatives.du_dx * width)
+                    .hypot(derivatives.dv_dx * height)
+                    .max((derivatives.du_dy * width).hypot(derivatives.dv_dy * height));
+                self.blend_levels(footprint, |level| self.bilinear(level, coords))
+            }
+            TextureFilter::Ewa => self.ewa(coords, derivatives),
+        }
+    }
+
+    /// Blends the two levels around the one whose texels are `footprint` texels of the full image wide
+    fn blend_levels(&self, footprint: f32, lookup: impl Fn(usize) -> Color) -> Color {
+        let last = (self.image.levels.len() - 1) as f32;
+        let level = footprint.max(1.0).log2().min(last);
+        let lower = level.floor();
+        let t = level - lower;
+        if t == 0.0 {
+            return lookup(lower as usize);
+        }
+        lookup(lower as usize) * (1.0 - t) + lookup(lower as usize + 1) * t
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Color {
+        let level = &self.image.levels[level];
+        let x = self.wrap.index(x, level.width);
+        let y = self.wrap.index(y, level.height);
+        level.texels[(y * level.width + x) as usize]
+    }
+
+    fn bilinear(&self, level: usize, coords: TextureCoords) -> Color {
+        let (width, height) = (self.image.levels[level].width, self.image.levels[level].height);
+        // texel centers lie at half-integer positions
+        let x = coords.x * width as f32 - 0.5;
+        let y = coords.y * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(level, x0, y0) * ((1.0 - tx) * (1.0 - ty))
+            + self.texel(level, x0 + 1, y0) * (tx * (1.0 - ty))
+            + self.texel(level, x0, y0 + 1) * ((1.0 - tx) * ty)
+            + self.texel(level, x0 + 1, y0 + 1) * (tx * ty)
+    }
+
+    /// Heckbert's elliptically weighted average, following pbrt's `MIPMap::EWA`
+    /// https://pbr-book.org/3ed-2018/Texture/Image_Texture#EllipticallyWeightedAverage
+    fn ewa(&self, coords: TextureCoords, derivatives: &TextureDerivatives) -> Color {
+        let (width, height) = (self.width() as f32, self.height() as f32);
+        // ellipse axes in texels of the full resolution image
+        let mut major = (derivatives.du_dx * width, derivatives.dv_dx * height);
+        let mut minor = (derivatives.du_dy * width, derivatives.dv_dy * height);
+        let length = |(x, y): (f32, f32)| x.hypot(y);
+        if length(major) < length(minor) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let major_length = length(major);
+        let mut minor_length = length(minor);
+        if minor_length == 0.0 {
+            return self.bilinear(0, coords);
+        }
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            minor_length *= scale;
+        }
+
+        // the level where the minor axis spans about a texel
+        self.blend_levels(minor_length, |level| {
+            let scale = (self.image.levels[level].width as f32 / width, self.image.levels[level].height as f32 / height);
+            let major = (major.0 * scale.0, major.1 * scale.1);
+            let minor = (minor.0 * scale.0, minor.1 * scale.1);
+            self.ewa_level(level, coords, major, minor)
+        })
+    }
+
+    /// EWA filtered lookup in one level, the axes are given in its texels
+    fn ewa_level(&self, level: usize, coords: TextureCoords, major: (f32, f32), minor: (f32, f32)) -> Color {
+        let s = coords.x * self.image.levels[level].width as f32 - 0.5;
+        let t = coords.y * self.image.levels[level].height as f32 - 0.5;
+
+        // implicit ellipse A s^2 + B s t + C t^2 = 1, widened by a texel to cover at least one
+        let mut a = major.1 * major.1 + minor.1 * minor.1 + 1.0;
+        let mut b = -2.0 * (major.0 * major.1 + minor.0 * minor.1);
+        let mut c = major.0 * major.0 + minor.0 * minor.0 + 1.0;
+        let inverse_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inverse_f;
+        b *= inverse_f;
+        c *= inverse_f;
+
+        // bounding box of the ellipse
+        let determinant = -b * b + 4.0 * a * c;
+        let half_width = 2.0 * (determinant * c).sqrt() / determinant;
+        let half_height = 2.0 * (determinant * a).sqrt() / determinant;
+        let (s0, s1) = ((s - half_width).ceil() as i64, (s + half_width).floor() as i64);
+        let (t0, t1) = ((t - half_height).ceil() as i64, (t + half_height).floor() as i64);
+
+        let mut sum = Color::BLACK;
+        let mut weights = 0.0;
+        for y in t0..=t1 {
+            let dt = y as f32 - t;
+            for x in s0..=s1 {
+                let ds = x as f32 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum += self.texel(level, x, y) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0.0 {
+            sum * (1.0 / weights)
+        } else {
+            self.bilinear(level, coords)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbImage;
+
+    use crate::object::{Coloration, Object};
+    use crate::scene_file::{load_scene_source, minimal_scene_toml, SceneError};
+
+    #[test]
+    fn materials_share_one_image_per_path() {
+        // never written, the cache has to serve it
+        let name = format!("raytracer-{}-shared.png", std::process::id());
+        let mut textures = TextureCache::new();
+        let image_path = std::env::temp_dir().join(&name);
+        let image = textures.insert(&image_path, &DynamicImage::ImageRgb8(RgbImage::new(2, 2)), true);
+
+        let sphere = |x: f64| {
+            format!(
+                "[[objects]]\ntype = \"sphere\"\ncenter = [{}, 0.0, -5.0]\nradius = 1.0\n\n\
+                 [objects.material.color]\ntexture = \"{}\"\n\n",
+                x, name
+            )
+        };
+        let source = minimal_scene_toml(&format!("{}{}", sphere(-2.0), sphere(2.0)));
+        let scene = load_scene_source(&source, &mut textures).unwrap();
+
+        assert_eq!(scene.objects.len(), 2);
+        for object in &scene.objects {
+            let Object::Sphere(sphere) = object else { panic!("expected a sphere") };
+            let Coloration::Texture { texture, .. } = &sphere.material.color else { panic!("expected a texture") };
+            assert!(Arc::ptr_eq(&texture.image, &image));
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("textures/missing.png");
+        let mut textures = TextureCache::new();
+        assert!(matches!(textures.load(&path, true), Err(ImageError::IoError(_))));
+
+        let source = minimal_scene_toml(&format!(
+            "[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = 1.0\n\n\
+             [objects.material.color]\ntexture = \"{}\"\n",
+            path.display()
+        ));
+        match load_scene_source(&source, &mut textures) {
+            Err(SceneError::Image { key, .. }) => assert_eq!(key, "objects[0].material.color.texture"),
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("a missing texture was accepted"),
+        }
+    }
+}
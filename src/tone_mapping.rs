@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::color::Color;
+
+/// Curves compressing scene radiance into the displayable [0, 1] range
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ToneMapOperator {
+    /// Cuts everything above 1
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Reinhard reaching 1 at the white point
+    ExtendedReinhard,
+    /// Stephen Hill's fit of the ACES reference and output transforms
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+impl ToneMapOperator {
+    pub const NAMES: &str = "`clamp`, `reinhard`, `extended_reinhard`, `aces`, `hable`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ExtendedReinhard => "extended_reinhard",
+            ToneMapOperator::Aces => "aces",
+            ToneMapOperator::Hable => "hable",
+        }
+    }
+
+    /// White point used when none is given
+    pub fn default_white_point(&self) -> f32 {
+        match self {
+            ToneMapOperator::ExtendedReinhard => 4.0,
+            // the original's white of 11.2 before its exposure bias
+            ToneMapOperator::Hable => 5.6,
+            _ => 1.0,
+        }
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "extended_reinhard" => Ok(ToneMapOperator::ExtendedReinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "hable" => Ok(ToneMapOperator::Hable),
+            _ => Err(format!(
+                "unknown tone mapping operator `{}`, expected one of {}",
+                name,
+                ToneMapOperator::NAMES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Maps linear radiance to linear display values before sRGB encoding
+#[derive(Clone, Copy)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, the radiance is scaled by 2^exposure
+    pub exposure: f32,
+    /// Smallest radiance mapped to pure white by `ExtendedReinhard` and `Hable`
+    pub white_point: f32,
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneMapOperator) -> ToneMapping {
+        ToneMapping {
+            operator,
+            exposure: 0.0,
+            white_point: operator.default_white_point(),
+        }
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let color = color * self.exposure.exp2();
+        let white = self.white_point;
+        match self.operator {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => per_channel(color, |x| x / (1.0 + x)),
+            ToneMapOperator::ExtendedReinhard => per_channel(color, |x| x * (1.0 + x / (white * white)) / (1.0 + x)),
+            ToneMapOperator::Aces => aces_fitted(color),
+            ToneMapOperator::Hable => {
+                // exposure bias of the original
+                per_channel(color, |x| hable(2.0 * x) / hable(2.0 * white))
+            }
+        }
+        .clamp()
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping::new(ToneMapOperator::Clamp)
+    }
+}
+
+fn per_channel(color: Color, curve: impl Fn(f32) -> f32) -> Color {
+    Color {
+        red: curve(color.red.max(0.0)),
+        green: curve(color.green.max(0.0)),
+        blue: curve(color.blue.max(0.0)),
+    }
+}
+
+/// http://filmicworlds.com/blog/filmic-tonemapping-operators/
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    // (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F, with D * E cancelled out so 0 maps to 0
+    x * (A * x + C * B - E / F * (A * x + B)) / (x * (A * x + B) + D * F)
+}
+
+/// https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+fn aces_fitted(color: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let rrt_and_odt_fit = |v: f32| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    };
+    let color = transform(&INPUT, color);
+    let color = Color {
+        red: rrt_and_odt_fit(color.red),
+        green: rrt_and_odt_fit(color.green),
+        blue: rrt_and_odt_fit(color.blue),
+    };
+    transform(&OUTPUT, color)
+}
+
+fn transform(matrix: &[[f32; 3]; 3], color: Color) -> Color {
+    let row = |r: &[f32; 3]| r[0] * color.red + r[1] * color.green + r[2] * color.blue;
+    Color {
+        red: row(&matrix[0]),
+        green: row(&matrix[1]),
+        blue: row(&matrix[2]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 5] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard,
+        ToneMapOperator::Aces,
+        ToneMapOperator::Hable,
+    ];
+
+    fn gray(value: f32) -> Color {
+        Color { red: value, green: value, blue: value }
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for operator in OPERATORS {
+            let black = ToneMapping::new(operator).apply(Color::BLACK);
+            assert_eq!([black.red, black.green, black.blue], [0.0; 3], "{}", operator);
+        }
+    }
+
+    #[test]
+    fn white_point_maps_to_white() {
+        for operator in [ToneMapOperator::ExtendedReinhard, ToneMapOperator::Hable] {
+            for white_point in [operator.default_white_point(), 2.0, 20.0] {
+                let tone_mapping = ToneMapping { white_point, ..ToneMapping::new(operator) };
+                let white = tone_mapping.apply(gray(white_point));
+                assert!((white.red - 1.0).abs() < 1e-5, "{} at {}: {}", operator, white_point, white.red);
+                assert!(tone_mapping.apply(gray(0.9 * white_point)).red < white.red);
+                assert_eq!(tone_mapping.apply(gray(2.0 * white_point)).red, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn curves_stay_in_range() {
+        for operator in OPERATORS {
+            let tone_mapping = ToneMapping::new(operator);
+            let mut previous = 0.0;
+            for i in 0..=100 {
+                let value = tone_mapping.apply(gray(i as f32 * 0.2)).green;
+                assert!((0.0..=1.0).contains(&value), "{}: {}", operator, value);
+                assert!(value >= previous, "{} isn't monotonic", operator);
+                previous = value;
+            }
+        }
+        assert_eq!(ToneMapping::new(ToneMapOperator::Reinhard).apply(gray(1.0)).red, 0.5);
+        assert_eq!(ToneMapping::new(ToneMapOperator::Clamp).apply(gray(0.25)).blue, 0.25);
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tone_mapping = ToneMapping { exposure: 2.0, ..ToneMapping::new(ToneMapOperator::Clamp) };
+        assert_eq!(tone_mapping.apply(gray(0.125)).red, 0.5);
+    }
+}
@@ -1,143 +1,659 @@
-use image::{DynamicImage, GenericImage};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::fmt;
+use std::str::FromStr;
 use vector3::Vector3;
 
-use crate::color::Color;
-use crate::object::Surface;
+use crate::bsdf::{principled, roughness_to_alpha, Bsdf};
+use crate::color::{beer_lambert, Color};
+use crate::framebuffer::Framebuffer;
+use crate::medium::MediumStack;
+use crate::object::{Object, Surface};
 use crate::point::Point;
-use crate::rendering::Ray;
-use crate::scene::{Intersection, Light, Scene};
+use crate::rendering::{Footprint, Ray};
+use crate::sampling::{stratified_samples, Rng};
+use crate::scene::{Intersection, Scene};
+use crate::spectrum::{self, Wavelengths};
 use crate::SHADOW_BIAS;
 
-fn shade_diffuse_color(
+/// Light transport algorithm used to compute the color of camera rays
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Integrator {
+    /// Direct lighting with perfect mirror and refraction bounces
+    Whitted,
+    /// Monte Carlo path tracing with indirect diffuse light
+    Path,
+}
+
+impl Integrator {
+    pub const NAMES: &str = "`whitted`, `path`";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::Whitted => "whitted",
+            Integrator::Path => "path",
+        }
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "whitted" => Ok(Integrator::Whitted),
+            "path" => Ok(Integrator::Path),
+            _ => Err(format!("unknown integrator `{}`, expected one of {}", name, Integrator::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Scattering of the object's surface at the hit point. Mirror and refraction parts of reflective
+/// and refractive surfaces are traced separately, their diffuse part is Lambertian.
+/// `outside` is the index of refraction of the medium around the object, colors are seen at the `wavelengths`.
+fn surface_bsdf(
+    object: &Object,
+    hit_point: &Point,
+    face: usize,
+    footprint: &Footprint,
+    outside: f32,
+    wavelengths: Option<Wavelengths>,
+) -> Bsdf {
+    let coords = object.surface_coords(hit_point, face, footprint);
+    let color = spectrum::at(object.material().color.color(&coords), wavelengths);
+    match object.surface_type() {
+        Surface::Conductor { roughness } => Bsdf::Conductor {
+            f0: color,
+            alpha: roughness_to_alpha(*roughness),
+        },
+        Surface::RoughDielectric { roughness, index, .. } => Bsdf::Dielectric {
+            tint: color,
+            alpha: roughness_to_alpha(*roughness),
+            index: (index.index(wavelengths) / outside) as f64,
+        },
+        Surface::Principled(parameters) => principled(color, parameters, &coords, outside),
+        _ => Bsdf::Lambert { color: color * object.albedo() },
+    }
+}
+
+/// Origin of rays leaving the hit point towards `direction`, moved off the surface to its side
+fn offset_origin(hit_point: &Point, surface_normal: &Vector3, direction: &Vector3) -> Point {
+    let bias = if surface_normal.dot(direction) < 0.0 { -SHADOW_BIAS } else { SHADOW_BIAS };
+    *hit_point + (*surface_normal * bias).into()
+}
+
+/// Light reaching the point `ray` hit from the scene's lights, scattered by `bsdf` back along the ray.
+/// Area lights are estimated with `Light::samples` stratified shadow rays.
+/// With `transparent_shadows`, the hit object and the media the ray is in, shadow rays pass through transparent objects,
+/// standing in for the light the Whitted tracer can't find through them; the path tracer finds it by following
+/// paths through them instead.
+fn direct_light(
     scene: &Scene,
-    intersection: &Intersection,
+    bsdf: &Bsdf,
     hit_point: &Point,
     surface_normal: &Vector3,
+    ray: &Ray,
+    transparent_shadows: Option<(&Object, MediumStack)>,
+    rng: &mut Rng,
 ) -> Color {
+    let wo = (Vector3::zero() - ray.direction).normalize();
     let mut color = Color::BLACK;
 
     for light_source in &scene.lights {
-        let direction_to_light = match light_source {
-            Light::Directional(light) => Vector3::zero() - light.direction,
-            Light::Spherical(light) => Vector3::from(light.position - *hit_point).normalize(),
+        let samples = light_source.samples();
+        let points = if samples == 1 {
+            vec![(rng.next_f64(), rng.next_f64())]
+        } else {
+            stratified_samples(samples, rng)
         };
 
-        let shadow_ray = Ray {
-            origin: *hit_point + (*surface_normal * SHADOW_BIAS).into(),
-            direction: direction_to_light,
-        };
-
-        let light_intensity = match light_source {
-            Light::Directional(light) => {
-                if let Some(_intersection) = scene.trace(&shadow_ray) {
-                    0.0
-                } else {
-                    light.intensity
-                }
-            }
-            Light::Spherical(light) => {
-                let d_sq = (*hit_point - light.position).magnitude_sq();
-                let d = d_sq.sqrt();
-                if let Some(intersection) = scene.trace(&shadow_ray) {
-                    if intersection.distance < d {
-                        light.intensity / (4.0 * std::f32::consts::PI * (d_sq as f32))
-                    } else {
-                        0.0
-                    }
-                } else {
-                    light.intensity / (4.0 * std::f32::consts::PI * (d_sq as f32))
-                }
+        let mut light_reflected = Color::BLACK;
+        for point in points {
+            let Some(sample) = light_source.sample(hit_point, point) else {
+                continue;
+            };
+            let scattered = bsdf.eval(surface_normal, &wo, &sample.direction);
+            if scattered.luminance() <= 0.0 {
+                continue;
             }
-        };
+            let shadow_ray = ray.next(offset_origin(hit_point, surface_normal, &sample.direction), sample.direction);
+            let transmittance = if let Some((object, media)) = transparent_shadows {
+                // light from behind the surface starts out on the other side of it
+                let media = next_media(object, surface_normal, &ray.direction, &sample.direction, media);
+                scene.transmittance(&shadow_ray, sample.distance, media)
+            } else if scene.trace(&shadow_ray).is_some_and(|intersection| intersection.distance < sample.distance) {
+                // something between the point and the light
+                continue;
+            } else {
+                Color::WHITE
+            };
+            light_reflected += scattered * transmittance * sample.intensity;
+        }
 
-        // Lambert's cosine law
-        let light_power = (surface_normal.dot(&direction_to_light) as f32) * light_intensity;
-        // TODO: figure out the derivation
-        // https://www.scratchapixel.com/lessons/3d-basic-rendering/introduction-to-shading/diffuse-lambertian-shading.html
-        // ^^^
-        let light_reflected = intersection.object.albedo() / std::f32::consts::PI;
-        color += intersection.object.color(&hit_point)
-            * light_source.color()
-            * light_power
-            * light_reflected;
+        color += light_reflected * spectrum::at(light_source.color(), ray.wavelengths) * (1.0 / samples as f32);
     }
 
-    color.clamp()
+    color
 }
 
-fn get_color(scene: &Scene, intersection: &Intersection, ray: &Ray, depth: u32) -> Color {
+/// `media` are the dielectrics the ray travels in
+fn get_color<'a>(
+    scene: &'a Scene,
+    intersection: &Intersection<'a>,
+    ray: &Ray,
+    media: MediumStack<'a>,
+    depth: u32,
+    rng: &mut Rng,
+) -> Color {
+    let (ray, dispersion) = &disperse(intersection.object, ray);
     let hit_point = ray.origin + (ray.direction * intersection.distance).into();
-    let surface_normal = intersection.object.surface_normal(&hit_point).normalize();
+    let normal = intersection.object.surface_normal(&hit_point, intersection.face).normalize();
+    let wo = (Vector3::zero() - ray.direction).normalize();
+    let footprint = ray.footprint(intersection.object, &hit_point, intersection.face, &normal);
+    let surface_normal = intersection.object.shading_normal(&hit_point, intersection.face, &footprint, normal);
+    let outside = media.outside_index(intersection.object, ray.wavelengths);
 
-    let diffuse_color = shade_diffuse_color(scene, intersection, &hit_point, &surface_normal);
+    let bsdf = surface_bsdf(intersection.object, &hit_point, intersection.face, &footprint, outside, ray.wavelengths);
+    let diffuse_color = direct_light(
+        scene,
+        &bsdf,
+        &hit_point,
+        &surface_normal,
+        ray,
+        Some((intersection.object, media)),
+        rng,
+    );
 
-    match intersection.object.surface_type() {
+    let color = match *intersection.object.surface_type() {
         Surface::Diffusive => diffuse_color,
         Surface::Reflective { reflectivity } => {
-            let color = diffuse_color * (1.0 - reflectivity)
-                + cast_ray(scene, &ray.reflect(hit_point, surface_normal), depth + 1)
-                    * reflectivity;
-            color
+            diffuse_color * (1.0 - reflectivity)
+                + cast_ray(scene, &ray.reflect(hit_point, surface_normal, &footprint), media, depth + 1, rng)
+                    * reflectivity
         }
         Surface::Refractive {
             transparency,
             index,
+            ..
         } => {
-            let reflection_ray = ray.reflect(hit_point, surface_normal);
-            let option_refraction_ray = ray.refract(hit_point, surface_normal, index);
+            let index = index.index(ray.wavelengths);
+            // reflected back to the side the ray came from, inside the object for rays leaving it
+            let reflection_ray = if normal.dot(&ray.direction) > 0.0 {
+                let facing_normal = Vector3::zero() - surface_normal;
+                let facing_footprint = ray.footprint(intersection.object, &hit_point, intersection.face, &facing_normal);
+                ray.reflect(hit_point, facing_normal, &facing_footprint)
+            } else {
+                ray.reflect(hit_point, surface_normal, &footprint)
+            };
+            let option_refraction_ray = ray.refract(hit_point, surface_normal, index, outside, &footprint);
 
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
+            let reflection_color = cast_ray(scene, &reflection_ray, media, depth + 1, rng);
             let refraction_color = if let Some(refraction_ray) = option_refraction_ray {
-                cast_ray(scene, &refraction_ray, depth + 1)
+                let refraction_media =
+                    next_media(intersection.object, &normal, &ray.direction, &refraction_ray.direction, media);
+                cast_ray(scene, &refraction_ray, refraction_media, depth + 1, rng)
             } else {
                 Color::BLACK
             };
 
             // Shlick's works better for some reason.
             #[allow(non_snake_case)]
-            let R_eff = ray.fresnel(surface_normal, index) as f32;
+            let R_eff = ray.fresnel(surface_normal, index, outside) as f32;
             // let R_eff = ray.schlicks(surface_normal, index) as f32;
             // let R_eff = 1.0;
 
-            // Debug
-            if R_eff < 0.0 {
-                println!("too small");
-            } else if R_eff > 1.0 {
-                println!("too big");
-            }
-
             let transmission_color = reflection_color * R_eff + refraction_color * (1.0 - R_eff);
 
-            let color = diffuse_color * (1.0 - transparency) + transmission_color * transparency;
-            color
+            diffuse_color * (1.0 - transparency) + transmission_color * transparency
         }
+        Surface::Conductor { .. } | Surface::RoughDielectric { .. } | Surface::Principled(_) => {
+            // one glossy bounce, the lights themselves are already in the direct part
+            let indirect_color = bsdf.glossy().sample(&surface_normal, &wo, rng).map_or(Color::BLACK, |sample| {
+                let bounce_ray = ray.next(offset_origin(&hit_point, &surface_normal, &sample.direction), sample.direction);
+                let bounce_media = next_media(intersection.object, &normal, &ray.direction, &bounce_ray.direction, media);
+                sample.weight * cast_indirect_ray(scene, &bounce_ray, bounce_media, depth + 1, rng)
+            });
+            diffuse_color + indirect_color
+        }
+    };
+    color * *dispersion
+}
+
+/// Dispersive surfaces send every wavelength its own way, spectral paths go on from them with the hero wavelength
+/// alone. Returns the ray with the wavelengths it goes on with, and the weight of its channels from the surface on.
+fn disperse(object: &Object, ray: &Ray) -> (Ray, Color) {
+    let mut wavelengths = ray.wavelengths;
+    let weight = match &mut wavelengths {
+        Some(wavelengths) if object.surface_type().is_dispersive() => wavelengths.terminate_secondary(),
+        _ => Color::WHITE,
+    };
+    (Ray { wavelengths, ..*ray }, weight)
+}
+
+/// Media a ray continues in after the hit point: it enters or leaves the object when it crosses the surface,
+/// they stay the same when it doesn't. `normal` is the unperturbed surface normal.
+fn next_media<'a>(
+    object: &'a Object,
+    normal: &Vector3,
+    incoming: &Vector3,
+    outgoing: &Vector3,
+    media: MediumStack<'a>,
+) -> MediumStack<'a> {
+    let (before, after) = (incoming.dot(normal), outgoing.dot(normal));
+    if before * after <= 0.0 {
+        media
+    } else if after < 0.0 {
+        media.entered(object)
+    } else {
+        media.left(object)
     }
 }
 
-fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
+/// The ray going on past a hit surface that doesn't separate media for it, the surface of a dielectric
+/// inside a higher priority one, together with the media it is in there. `None` for actual interfaces.
+fn pass_through<'a>(intersection: &Intersection<'a>, ray: &Ray, media: MediumStack<'a>) -> Option<(Ray, MediumStack<'a>)> {
+    let object = intersection.object;
+    object.surface_type().index(None)?;
+    let hit_point = ray.origin + (ray.direction * intersection.distance).into();
+    let normal = object.surface_normal(&hit_point, intersection.face);
+    let entering = normal.dot(&ray.direction) < 0.0;
+    if media.is_interface(object, entering) {
+        return None;
+    }
+    let media = if entering { media.entered(object) } else { media.left(object) };
+    Some((ray.pass_through(hit_point, normal.normalize()), media))
+}
+
+/// Whitted-style tracer: direct light on diffuse surfaces plus mirror and refraction bounces,
+/// dimmed by the absorption of the `media` the ray travels in
+fn cast_ray<'a>(scene: &'a Scene, ray: &Ray, media: MediumStack<'a>, depth: u32, rng: &mut Rng) -> Color {
     if depth == scene.max_recursion_depth {
         return Color::BLACK;
     }
 
     let intersection = scene.trace(ray);
+    if let Some((distance, light)) = scene.trace_light(ray) {
+        if intersection.as_ref().is_none_or(|intersection| distance < intersection.distance) {
+            return spectrum::at(light.radiance(), ray.wavelengths) * beer_lambert(media.absorption(ray.wavelengths), distance);
+        }
+    }
     if let Some(blabla) = intersection {
-        get_color(scene, &blabla, ray, depth)
+        let color = match pass_through(&blabla, ray, media) {
+            Some((ray, media)) => cast_ray(scene, &ray, media, depth, rng),
+            None => get_color(scene, &blabla, ray, media, depth, rng),
+        };
+        color * beer_lambert(media.absorption(ray.wavelengths), blabla.distance)
+    } else {
+        spectrum::at(scene.environment.radiance(&ray.direction), ray.wavelengths)
+            * beer_lambert(media.absorption(ray.wavelengths), f64::INFINITY)
+    }
+}
+
+/// Like `cast_ray`, but the ray passes through the lights:
+/// for bounces off surfaces whose direct lighting was already computed
+fn cast_indirect_ray<'a>(scene: &'a Scene, ray: &Ray, media: MediumStack<'a>, depth: u32, rng: &mut Rng) -> Color {
+    if depth == scene.max_recursion_depth {
+        return Color::BLACK;
+    }
+
+    match scene.trace(ray) {
+        Some(intersection) => {
+            let color = match pass_through(&intersection, ray, media) {
+                Some((ray, media)) => cast_indirect_ray(scene, &ray, media, depth, rng),
+                None => get_color(scene, &intersection, ray, media, depth, rng),
+            };
+            color * beer_lambert(media.absorption(ray.wavelengths), intersection.distance)
+        }
+        None => {
+            spectrum::at(scene.environment.radiance(&ray.direction), ray.wavelengths)
+                * beer_lambert(media.absorption(ray.wavelengths), f64::INFINITY)
+        }
+    }
+}
+
+/// Environment light scattered by `bsdf` back along `ray`, sampled by the environment's own density.
+/// BSDF bounces can reach the environment as well, the two estimates are combined
+/// with multiple importance sampling.
+fn environment_light(
+    scene: &Scene,
+    bsdf: &Bsdf,
+    hit_point: &Point,
+    surface_normal: &Vector3,
+    ray: &Ray,
+    rng: &mut Rng,
+) -> Color {
+    let wo = &(Vector3::zero() - ray.direction).normalize();
+    if scene.environment.is_black() {
+        return Color::BLACK;
+    }
+    let Some(sample) = scene.environment.sample((rng.next_f64(), rng.next_f64())) else {
+        return Color::BLACK;
+    };
+    let scattered = bsdf.eval(surface_normal, wo, &sample.direction);
+    if scattered.luminance() <= 0.0 {
+        return Color::BLACK;
+    }
+    let shadow_ray = ray.next(offset_origin(hit_point, surface_normal, &sample.direction), sample.direction);
+    if scene.trace(&shadow_ray).is_some() {
+        return Color::BLACK;
+    }
+    let weight = power_heuristic(sample.pdf, bsdf.pdf(surface_normal, wo, &sample.direction));
+    scattered * spectrum::at(sample.radiance, ray.wavelengths) * (weight / sample.pdf) as f32
+}
+
+/// Multiple importance sampling weight of the strategy with density `f` against one with `g`
+fn power_heuristic(f: f64, g: f64) -> f64 {
+    let (f, g) = (f * f, g * g);
+    if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
+}
+
+/// Path length after which Russian roulette starts terminating paths
+const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+
+/// Unidirectional path tracer.
+/// Diffuse and microfacet surfaces are lit by next-event estimation towards the lights and bounce
+/// the path in a direction importance sampled from their BSDF, the surface's diffuse, mirror and refraction
+/// parts are picked at random with the weights the Whitted tracer blends them with.
+fn trace_path(scene: &Scene, primary_ray: Ray, rng: &mut Rng) -> Color {
+    let mut radiance = Color::BLACK;
+    let mut throughput = Color::WHITE;
+    let mut ray = primary_ray;
+    let mut specular_bounce = true;
+    // density the last BSDF bounce picked its direction with
+    let mut bounce_pdf = 0.0;
+    // dielectrics the path is in
+    let mut media = MediumStack::default();
+
+    let mut depth = 0;
+    while depth < scene.max_recursion_depth {
+        let intersection = scene.trace(&ray);
+        if let Some((distance, light)) = scene.trace_light(&ray) {
+            if intersection.as_ref().is_none_or(|intersection| distance < intersection.distance) {
+                // after diffuse bounces the light was already counted by next-event estimation
+                if specular_bounce {
+                    radiance += throughput
+                        * spectrum::at(light.radiance(), ray.wavelengths)
+                        * beer_lambert(media.absorption(ray.wavelengths), distance);
+                }
+                break;
+            }
+        }
+        let Some(intersection) = intersection else {
+            let weight = if specular_bounce {
+                1.0
+            } else {
+                power_heuristic(bounce_pdf, scene.environment.pdf(&ray.direction))
+            };
+            radiance += throughput
+                * spectrum::at(scene.environment.radiance(&ray.direction), ray.wavelengths)
+                * beer_lambert(media.absorption(ray.wavelengths), f64::INFINITY)
+                * weight as f32;
+            break;
+        };
+        throughput = throughput * beer_lambert(media.absorption(ray.wavelengths), intersection.distance);
+        if let Some((next_ray, next_media)) = pass_through(&intersection, &ray, media) {
+            // not a bounce, the path goes on as it was
+            (ray, media) = (next_ray, next_media);
+            continue;
+        }
+        let object = intersection.object;
+        let dispersion;
+        (ray, dispersion) = disperse(object, &ray);
+        throughput = throughput * dispersion;
+        let hit_point = ray.origin + (ray.direction * intersection.distance).into();
+        let normal = object.surface_normal(&hit_point, intersection.face).normalize();
+        let footprint = ray.footprint(object, &hit_point, intersection.face, &normal);
+        let surface_normal = object.shading_normal(&hit_point, intersection.face, &footprint, normal);
+        let outside = media.outside_index(object, ray.wavelengths);
+        // normal on the side the ray came from
+        let facing_normal = if normal.dot(&ray.direction) > 0.0 {
+            Vector3::zero() - surface_normal
+        } else {
+            surface_normal
+        };
+        // reflections off the side the ray came from need the normal's changes in that orientation too
+        let facing_footprint = || ray.footprint(object, &hit_point, intersection.face, &facing_normal);
+
+        let specular_ray = match *object.surface_type() {
+            Surface::Diffusive | Surface::Conductor { .. } | Surface::RoughDielectric { .. } | Surface::Principled(_) => {
+                None
+            }
+            Surface::Reflective { reflectivity } => {
+                (rng.next_f64() < reflectivity as f64).then(|| ray.reflect(hit_point, facing_normal, &facing_footprint()))
+            }
+            Surface::Refractive { transparency, index, .. } => (rng.next_f64() < transparency as f64).then(|| {
+                let index = index.index(ray.wavelengths);
+                let reflectance = ray.fresnel(surface_normal, index, outside);
+                match ray.refract(hit_point, surface_normal, index, outside, &footprint) {
+                    Some(refraction_ray) if rng.next_f64() >= reflectance => refraction_ray,
+                    _ => ray.reflect(hit_point, facing_normal, &facing_footprint()),
+                }
+            }),
+        };
+
+        specular_bounce = specular_ray.is_some();
+        let next_ray = match specular_ray {
+            Some(specular_ray) => specular_ray,
+            None => {
+                let wo = (Vector3::zero() - ray.direction).normalize();
+                let bsdf = surface_bsdf(object, &hit_point, intersection.face, &footprint, outside, ray.wavelengths);
+                radiance += throughput * direct_light(scene, &bsdf, &hit_point, &surface_normal, &ray, None, rng);
+                radiance += throughput * environment_light(scene, &bsdf, &hit_point, &surface_normal, &ray, rng);
+                let Some(sample) = bsdf.sample(&surface_normal, &wo, rng) else {
+                    break;
+                };
+                throughput = throughput * sample.weight;
+                bounce_pdf = sample.pdf;
+                ray.next(offset_origin(&hit_point, &surface_normal, &sample.direction), sample.direction)
+            }
+        };
+        media = next_media(object, &normal, &ray.direction, &next_ray.direction, media);
+        ray = next_ray;
+        depth += 1;
+
+        if depth >= RUSSIAN_ROULETTE_DEPTH {
+            let survival = throughput.red.max(throughput.green).max(throughput.blue).min(0.95);
+            if rng.next_f64() as f32 >= survival {
+                break;
+            }
+            throughput = throughput * (1.0 / survival);
+        }
+    }
+
+    radiance
+}
+
+/// Side of the square tiles the frame is split into
+const TILE_SIZE: u32 = 32;
+
+/// Rectangular part of the frame rendered as a single job
+#[derive(Clone, Copy)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Traces `scene.samples_per_pixel` jittered rays through the pixel (a single one goes through its center)
+/// and splats them onto every pixel within the filter radius
+fn render_pixel(scene: &Scene, x: u32, y: u32, buffer: &mut Framebuffer) {
+    let filter = &scene.filter;
+    let mut rng = Rng::for_pixel(x, y);
+    let offsets = if scene.samples_per_pixel <= 1 {
+        vec![(0.5, 0.5)]
     } else {
-        Color::BLACK
+        stratified_samples(scene.samples_per_pixel, &mut rng)
+    };
+    for (offset_x, offset_y) in offsets {
+        let sample_x = x as f64 + offset_x;
+        let sample_y = y as f64 + offset_y;
+        let mut ray = Ray::create_prime(sample_x, sample_y, scene);
+        // each sample covers a part of the pixel
+        ray.scale_differentials(1.0 / (scene.samples_per_pixel.max(1) as f64).sqrt());
+        let wavelengths = scene.spectral.then(|| Wavelengths::sample(rng.next_f64()));
+        ray.wavelengths = wavelengths;
+        let color = match scene.integrator {
+            Integrator::Whitted => cast_ray(scene, &ray, MediumStack::default(), 0, &mut rng),
+            Integrator::Path => trace_path(scene, ray, &mut rng),
+        };
+        let color = wavelengths.map_or(color, |wavelengths| wavelengths.to_rgb(color));
+
+        let min_x = (sample_x - 0.5 - filter.radius).ceil() as i64;
+        let max_x = (sample_x - 0.5 + filter.radius).floor() as i64;
+        let min_y = (sample_y - 0.5 - filter.radius).ceil() as i64;
+        let max_y = (sample_y - 0.5 + filter.radius).floor() as i64;
+        for pixel_y in min_y..=max_y {
+            for pixel_x in min_x..=max_x {
+                let weight = filter.weight(
+                    pixel_x as f64 + 0.5 - sample_x,
+                    pixel_y as f64 + 0.5 - sample_y,
+                );
+                if weight != 0.0 {
+                    buffer.add(pixel_x, pixel_y, color, weight as f32);
+                }
+            }
+        }
+    }
+}
+
+/// Renders the tile's pixels into a buffer extended by the filter radius,
+/// so samples near the tile border can reach the neighbouring tiles
+fn render_tile(scene: &Scene, tile: Tile) -> Framebuffer {
+    let margin = scene.filter.radius.ceil() as u32;
+    let mut buffer = Framebuffer::with_origin(
+        tile.x as i64 - margin as i64,
+        tile.y as i64 - margin as i64,
+        tile.width + 2 * margin,
+        tile.height + 2 * margin,
+    );
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+            render_pixel(scene, x, y, &mut buffer);
+        }
     }
+    buffer
 }
 
-/// Actual rendering process: shooting rays
-pub fn render(scene: &Scene) -> DynamicImage {
-    let mut image = DynamicImage::new_rgb8(scene.width, scene.height);
+/// Actual rendering process: shooting rays.
+/// Tiles are rendered on `scene.threads` threads (0 means one per core)
+/// and merged in a fixed order, so the result doesn't depend on the thread count.
+pub fn render(scene: &Scene) -> Framebuffer {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(scene.threads)
+        .build()
+        .expect("failed to create the rendering thread pool");
+
+    let rendered: Vec<Framebuffer> = pool.install(|| {
+        tiles(scene.width, scene.height)
+            .into_par_iter()
+            .map(|tile| render_tile(scene, tile))
+            .collect()
+    });
+
+    let mut framebuffer = Framebuffer::new(scene.width, scene.height);
+    for buffer in &rendered {
+        framebuffer.merge(buffer);
+    }
+    framebuffer
+}
 
-    for x in 0..scene.width {
-        for y in 0..scene.height {
-            let ray = Ray::create_prime(x, y, scene);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::filter::{Filter, FilterKind};
+    use crate::object::{Material, Plane, Sphere};
+    use crate::scene::{Light, SphericalLight};
+    use crate::ALBEDO;
 
-            image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
+    fn scene(threads: usize) -> Scene {
+        let sphere = |x: f64, surface: Surface| {
+            Object::Sphere(Sphere {
+                center: Point { x, y: 0.0, z: -5.0 },
+                radius: 1.0,
+                material: Material::from_color(Color { red: 0.9, green: 0.6, blue: 0.3 }, ALBEDO, surface),
+            })
+        };
+        Scene {
+            // several tiles, the last ones cut short
+            width: 70,
+            height: 40,
+            objects: vec![
+                sphere(-1.2, Surface::Diffusive),
+                sphere(1.2, Surface::Reflective { reflectivity: 0.5 }),
+                Object::Plane(Plane {
+                    origin: Point { x: 0.0, y: -1.0, z: 0.0 },
+                    normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                    material: Material::from_color(Color::WHITE, ALBEDO, Surface::Diffusive),
+                }),
+            ],
+            lights: vec![Light::Spherical(SphericalLight {
+                position: Point { x: 2.0, y: 4.0, z: -2.0 },
+                radius: 0.5,
+                color: Color::WHITE,
+                intensity: 500.0,
+                samples: 2,
+            })],
+            environment: Environment::Gradient { bottom: Color::BLACK, top: Color { red: 0.2, green: 0.3, blue: 0.5 } },
+            integrator: Integrator::Path,
+            max_recursion_depth: 4,
+            samples_per_pixel: 4,
+            // reaches into the neighbouring tiles, with negative lobes
+            filter: Filter { kind: FilterKind::Mitchell, radius: 2.0 },
+            threads,
+            ..Scene::empty()
         }
     }
 
-    image
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let sum = |framebuffer: &Framebuffer| {
+            let mut sum = [0.0f64; 3];
+            for y in 0..framebuffer.height {
+                for x in 0..framebuffer.width {
+                    let color = framebuffer.pixel(x, y);
+                    sum[0] += color.red as f64;
+                    sum[1] += color.green as f64;
+                    sum[2] += color.blue as f64;
+                }
+            }
+            sum
+        };
+        let single = render(&scene(1));
+        let multi = render(&scene(4));
+        assert!(sum(&single).iter().all(|&channel| channel > 0.0));
+        assert_eq!(sum(&single), sum(&multi));
+        for y in 0..single.height {
+            for x in 0..single.width {
+                let (a, b) = (single.pixel(x, y), multi.pixel(x, y));
+                assert_eq!([a.red, a.green, a.blue], [b.red, b.green, b.blue], "pixel ({}, {})", x, y);
+            }
+        }
+    }
 }